@@ -0,0 +1,588 @@
+//! Browser discovery from XDG desktop entries
+//!
+//! Installed browsers are found by scanning the `applications` directories of
+//! the XDG data dirs, plus the Flatpak and Snap export directories, for
+//! entries in the `WebBrowser` category or handling `x-scheme-handler/https`.
+//! Each browser is launched through its own `Exec` line, so Flatpak and Snap
+//! wrappers (and their file forwarding) keep working. The scan runs once;
+//! browsers installed later are found after a restart.
+//!
+//! Browsers the scan can't know about, or that need extra arguments for a
+//! private window or a separate profile, are configured as
+//! [`CustomBrowser`]s.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// A browser's `.desktop` entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesktopEntry {
+    /// Desktop file ID, e.g. `org.mozilla.firefox.desktop`
+    pub id: String,
+    pub name: String,
+    /// The unescaped `Exec` line, field codes still in place
+    pub exec: String,
+    pub categories: Vec<String>,
+    pub mime_types: Vec<String>,
+    /// `NoDisplay` or `Hidden` is set
+    pub hidden: bool,
+}
+
+impl DesktopEntry {
+    pub fn is_browser(&self) -> bool {
+        self.categories.iter().any(|c| c == "WebBrowser")
+            || self
+                .mime_types
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case("x-scheme-handler/https"))
+    }
+
+    /// Program and arguments to open `target` with this entry
+    pub fn command_line(&self, target: &str) -> Vec<String> {
+        exec_args(&self.exec, target)
+    }
+}
+
+/// Parse the `[Desktop Entry]` group of a `.desktop` file
+pub fn parse_desktop_entry(id: &str, content: &str) -> Option<DesktopEntry> {
+    let mut in_main_group = false;
+    let mut name = None;
+    let mut exec = None;
+    let mut is_application = false;
+    let mut categories = Vec::new();
+    let mut mime_types = Vec::new();
+    let mut hidden = false;
+
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_main_group = line == "[Desktop Entry]";
+            continue;
+        }
+        if !in_main_group || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        // Localized keys such as Name[de] are skipped
+        match key.trim() {
+            "Type" => is_application = value.trim() == "Application",
+            "Name" => name = Some(unescape_value(value.trim())),
+            "Exec" => exec = Some(unescape_value(value.trim())),
+            "Categories" => categories = split_list(value),
+            "MimeType" => mime_types = split_list(value),
+            "NoDisplay" | "Hidden" => hidden |= value.trim() == "true",
+            _ => {}
+        }
+    }
+
+    if !is_application {
+        return None;
+    }
+
+    Some(DesktopEntry {
+        id: id.to_string(),
+        name: name?,
+        exec: exec?,
+        categories,
+        mime_types,
+        hidden,
+    })
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Undo the escapes of desktop entry string values (`\s`, `\n`, `\\`, ...)
+fn unescape_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => out.push(' '),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            // Exec quoting comes after this, so `\\"` in the file is `\"` there
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                // Leave other escapes for the Exec quoting rules
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+
+    out
+}
+
+/// Split an `Exec` line into arguments and expand its field codes with
+/// `target`. The target is appended if the line has no file or URL code.
+pub fn exec_args(exec: &str, target: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut substituted = false;
+
+    for word in split_exec(exec) {
+        // A lone %F / %U may expand to several arguments; we only have one
+        if matches!(word.as_str(), "%f" | "%F" | "%u" | "%U") {
+            args.push(target.to_string());
+            substituted = true;
+            continue;
+        }
+        // Deprecated or icon/name codes expand to nothing here
+        if matches!(
+            word.as_str(),
+            "%i" | "%c" | "%k" | "%d" | "%D" | "%n" | "%N" | "%v" | "%m"
+        ) {
+            continue;
+        }
+
+        let mut arg = String::with_capacity(word.len());
+        let mut chars = word.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                arg.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('%') => arg.push('%'),
+                Some('f' | 'F' | 'u' | 'U') => {
+                    arg.push_str(target);
+                    substituted = true;
+                }
+                _ => {}
+            }
+        }
+        args.push(arg);
+    }
+
+    if !substituted && !args.is_empty() {
+        args.push(target.to_string());
+    }
+
+    args
+}
+
+/// Split an `Exec` line on unquoted whitespace; double-quoted arguments may
+/// escape `"`, `` ` ``, `$` and `\` with a backslash
+fn split_exec(exec: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut chars = exec.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            '\\' if quoted => {
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+
+    words
+}
+
+/// Directories that may contain `.desktop` files, most important first
+pub fn application_dirs() -> Vec<PathBuf> {
+    let home = dirs::home_dir().unwrap_or_default();
+
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .unwrap_or_else(|| home.join(".local/share"));
+
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+
+    let mut dirs: Vec<PathBuf> = std::iter::once(data_home.clone())
+        .chain(data_dirs.split(':').map(PathBuf::from))
+        .chain([
+            // Not always in XDG_DATA_DIRS, e.g. when started from a terminal
+            data_home.join("flatpak/exports/share"),
+            PathBuf::from("/var/lib/flatpak/exports/share"),
+            PathBuf::from("/var/lib/snapd/desktop"),
+        ])
+        .filter(|p| p.is_absolute())
+        .map(|p| p.join("applications"))
+        .collect();
+
+    let mut seen = HashSet::new();
+    dirs.retain(|dir| seen.insert(dir.clone()));
+    dirs
+}
+
+/// All desktop entries, scanned on first use
+fn all_entries() -> &'static [DesktopEntry] {
+    static ENTRIES: OnceLock<Vec<DesktopEntry>> = OnceLock::new();
+    ENTRIES.get_or_init(scan_entries)
+}
+
+/// Read all desktop entries, the first one of each ID winning
+fn scan_entries() -> Vec<DesktopEntry> {
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+
+    for dir in application_dirs() {
+        let mut files = Vec::new();
+        collect_desktop_files(&dir, &dir, &mut files);
+
+        for (id, path) in files {
+            // An entry in a more important dir shadows the others, even if
+            // it hides the application
+            if !seen.insert(id.clone()) {
+                continue;
+            }
+            if let Some(entry) = fs::read_to_string(&path)
+                .ok()
+                .and_then(|content| parse_desktop_entry(&id, &content))
+            {
+                entries.push(entry);
+            }
+        }
+    }
+
+    entries
+}
+
+/// Find `.desktop` files below `dir`; IDs use `-` for subdirectories
+fn collect_desktop_files(root: &Path, dir: &Path, out: &mut Vec<(String, PathBuf)>) {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let mut paths: Vec<PathBuf> = read_dir.flatten().map(|e| e.path()).collect();
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            collect_desktop_files(root, &path, out);
+        } else if path.extension().and_then(|e| e.to_str()) == Some("desktop") {
+            if let Ok(relative) = path.strip_prefix(root) {
+                let id = relative.to_string_lossy().replace('/', "-");
+                out.push((id, path));
+            }
+        }
+    }
+}
+
+/// Visible browsers, sorted by name
+pub fn installed_browsers() -> Vec<DesktopEntry> {
+    let mut browsers: Vec<_> = all_entries()
+        .iter()
+        .filter(|e| e.is_browser() && !e.hidden)
+        .cloned()
+        .collect();
+    browsers.sort_by_key(|e| e.name.to_lowercase());
+    browsers
+}
+
+/// Look up a desktop entry by ID
+pub fn find(id: &str) -> Option<DesktopEntry> {
+    all_entries().iter().find(|e| e.id == id).cloned()
+}
+
+/// A user-defined browser from `custom_browsers` in the config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CustomBrowser {
+    pub name: String,
+    /// Command template, e.g. `firefox --new-window {url}`
+    pub command: String,
+    /// Arguments for a private window, e.g. `["--private-window"]`
+    #[serde(default)]
+    pub private_args: Option<Vec<String>>,
+    /// Arguments for a specific profile, e.g. `["-P", "work"]`
+    #[serde(default)]
+    pub profile_args: Option<Vec<String>>,
+}
+
+impl CustomBrowser {
+    /// Program and arguments to open `target`, with the private window
+    /// and/or profile arguments if asked for. `None` if this browser has no
+    /// such arguments configured.
+    pub fn command_line(&self, target: &str, private: bool, profile: bool) -> Option<Vec<String>> {
+        let mut extra = Vec::new();
+        if private {
+            extra.extend(self.private_args.clone()?);
+        }
+        if profile {
+            extra.extend(self.profile_args.clone()?);
+        }
+        Some(expand_template(&self.command, &extra, target))
+    }
+}
+
+/// Split a browser command template, substituting `{url}` (or `{path}`) with
+/// `target`. `extra` goes where `{args}` is, or else right before the
+/// target; the target is appended if the template doesn't mention it.
+pub fn expand_template(template: &str, extra: &[String], target: &str) -> Vec<String> {
+    let has_placeholder = |word: &str| word.contains("{url}") || word.contains("{path}");
+    let has_args_slot = template.split_whitespace().any(|w| w == "{args}");
+    let mut args = Vec::new();
+    let mut extra_placed = false;
+    let mut target_placed = false;
+
+    for word in template.split_whitespace() {
+        if word == "{args}" {
+            args.extend_from_slice(extra);
+            extra_placed = true;
+        } else if has_placeholder(word) {
+            if !extra_placed && !has_args_slot {
+                args.extend_from_slice(extra);
+                extra_placed = true;
+            }
+            args.push(word.replace("{url}", target).replace("{path}", target));
+            target_placed = true;
+        } else {
+            args.push(word.to_string());
+        }
+    }
+
+    if args.is_empty() {
+        return args;
+    }
+    if !extra_placed {
+        args.extend_from_slice(extra);
+    }
+    if !target_placed {
+        args.push(target.to_string());
+    }
+
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://example.com/a b";
+
+    #[test]
+    fn split_exec_handles_quotes() {
+        let cases: &[(&str, &[&str])] = &[
+            ("firefox %u", &["firefox", "%u"]),
+            ("  firefox   --new-window  ", &["firefox", "--new-window"]),
+            (
+                r#""/opt/My Browser/browser" --name="a \"b\"" %U"#,
+                &["/opt/My Browser/browser", "--name=a \"b\"", "%U"],
+            ),
+            (
+                r#"sh -c "echo \$HOME \\ \`x\`""#,
+                &["sh", "-c", "echo $HOME \\ `x`"],
+            ),
+            (r#"browser """#, &["browser", ""]),
+            ("", &[]),
+        ];
+
+        for (exec, expected) in cases {
+            assert_eq!(&split_exec(exec), expected, "{}", exec);
+        }
+    }
+
+    #[test]
+    fn exec_args_expand_field_codes() {
+        let cases: &[(&str, &[&str])] = &[
+            ("firefox %u", &["firefox", URL]),
+            ("chromium-browser %U", &["chromium-browser", URL]),
+            // Flatpak file forwarding
+            (
+                "/usr/bin/flatpak run --branch=stable --arch=x86_64 --command=firefox \
+                 --file-forwarding org.mozilla.firefox @@u %u @@",
+                &[
+                    "/usr/bin/flatpak",
+                    "run",
+                    "--branch=stable",
+                    "--arch=x86_64",
+                    "--command=firefox",
+                    "--file-forwarding",
+                    "org.mozilla.firefox",
+                    "@@u",
+                    URL,
+                    "@@",
+                ],
+            ),
+            // Snap
+            (
+                "env BAMF_DESKTOP_FILE_HINT=/var/lib/snapd/desktop/applications/\
+                 chromium_chromium.desktop /snap/bin/chromium %U",
+                &[
+                    "env",
+                    "BAMF_DESKTOP_FILE_HINT=/var/lib/snapd/desktop/applications/chromium_chromium.desktop",
+                    "/snap/bin/chromium",
+                    URL,
+                ],
+            ),
+            ("browser --title=100%% %u", &["browser", "--title=100%", URL]),
+            ("browser --open=%u", &["browser", &format!("--open={}", URL)]),
+            ("browser %i %c %k --new-window %u", &["browser", "--new-window", URL]),
+            // No field code: the target goes last
+            ("browser --new-window", &["browser", "--new-window", URL]),
+            ("", &[]),
+        ];
+
+        for (exec, expected) in cases {
+            assert_eq!(&exec_args(exec, URL), expected, "{}", exec);
+        }
+    }
+
+    #[test]
+    fn parses_the_main_group() {
+        let content = r#"# Firefox
+[Desktop Entry]
+Version=1.0
+Name=Firefox\sWeb Browser
+Name[de]=Firefox-Webbrowser
+# Exec=ignored
+Exec=firefox --name="Firefox \\"Nightly\\"" %u
+Type=Application
+Categories=Network;WebBrowser;
+MimeType=text/html;x-scheme-handler/http;x-scheme-handler/https;
+
+[Desktop Action new-private-window]
+Name=New Private Window
+Exec=firefox --private-window %u
+NoDisplay=true
+"#;
+
+        let entry = parse_desktop_entry("firefox.desktop", content).unwrap();
+        assert_eq!(entry.id, "firefox.desktop");
+        assert_eq!(entry.name, "Firefox Web Browser");
+        assert_eq!(entry.categories, ["Network", "WebBrowser"]);
+        assert_eq!(entry.mime_types.len(), 3);
+        assert!(entry.is_browser());
+        assert!(!entry.hidden);
+        assert_eq!(
+            entry.command_line(URL),
+            ["firefox", "--name=Firefox \"Nightly\"", URL]
+        );
+    }
+
+    #[test]
+    fn skips_entries_that_are_not_applications() {
+        let link = "[Desktop Entry]\nType=Link\nName=Docs\nURL=https://example.com\n";
+        assert_eq!(parse_desktop_entry("docs.desktop", link), None);
+
+        let no_exec = "[Desktop Entry]\nType=Application\nName=Broken\n";
+        assert_eq!(parse_desktop_entry("broken.desktop", no_exec), None);
+
+        let hidden = "[Desktop Entry]\nType=Application\nName=Old\nExec=old %u\nHidden=true\n\
+                      MimeType=x-scheme-handler/https\n";
+        let entry = parse_desktop_entry("old.desktop", hidden).unwrap();
+        assert!(entry.hidden && entry.is_browser());
+    }
+
+    #[test]
+    fn expand_template_places_url_and_args() {
+        let private = ["--private-window".to_string()];
+        let cases: &[(&str, &[String], &[&str])] = &[
+            ("firefox {url}", &[], &["firefox", URL]),
+            (
+                "firefox --new-window {url}",
+                &private,
+                &["firefox", "--new-window", "--private-window", URL],
+            ),
+            (
+                "firefox {args} --new-window {url}",
+                &private,
+                &["firefox", "--private-window", "--new-window", URL],
+            ),
+            (
+                "firefox {url} {args}",
+                &private,
+                &["firefox", URL, "--private-window"],
+            ),
+            (
+                "browser --open={url}",
+                &private,
+                &["browser", "--private-window", &format!("--open={}", URL)],
+            ),
+            ("viewer {path}", &[], &["viewer", URL]),
+            // No placeholder: extra arguments, then the target
+            ("firefox", &private, &["firefox", "--private-window", URL]),
+            (
+                "firefox {args}",
+                &private,
+                &["firefox", "--private-window", URL],
+            ),
+            ("", &private, &[]),
+        ];
+
+        for (template, extra, expected) in cases {
+            assert_eq!(
+                &expand_template(template, extra, URL),
+                expected,
+                "{}",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn custom_browser_needs_arguments_for_the_mode() {
+        let mut browser = CustomBrowser {
+            name: "Firefox".to_string(),
+            command: "firefox {args} {url}".to_string(),
+            private_args: None,
+            profile_args: None,
+        };
+
+        assert_eq!(
+            browser.command_line(URL, false, false),
+            Some(vec!["firefox".to_string(), URL.to_string()])
+        );
+        assert_eq!(browser.command_line(URL, true, false), None);
+        assert_eq!(browser.command_line(URL, false, true), None);
+
+        browser.private_args = Some(vec!["--private-window".to_string()]);
+        browser.profile_args = Some(vec!["-P".to_string(), "work".to_string()]);
+
+        let line = |private, profile| browser.command_line(URL, private, profile).unwrap();
+        assert_eq!(line(false, false), ["firefox", URL]);
+        assert_eq!(line(true, false), ["firefox", "--private-window", URL]);
+        assert_eq!(line(false, true), ["firefox", "-P", "work", URL]);
+        assert_eq!(
+            line(true, true),
+            ["firefox", "--private-window", "-P", "work", URL]
+        );
+
+        browser.profile_args = None;
+        assert_eq!(browser.command_line(URL, true, true), None);
+    }
+}
@@ -0,0 +1,165 @@
+//! Microphone, camera and screen in-use indicator
+//!
+//! WebKit reports what the page is capturing through the webview's
+//! capture-state properties. Every change reaches the event loop as a
+//! [`CaptureState`], which adds a suffix to the window title and updates an
+//! overlay badge. Ctrl+Shift+K, or clicking the badge, stops all capture.
+
+use serde::Serialize;
+
+/// State of one capture device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum Capture {
+    #[default]
+    Off,
+    Active,
+    /// Still held by the page, but sending silence or black frames
+    Muted,
+}
+
+/// What the page is capturing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CaptureState {
+    pub microphone: Capture,
+    pub camera: Capture,
+    pub screen: Capture,
+}
+
+impl CaptureState {
+    pub fn is_active(&self) -> bool {
+        *self != CaptureState::default()
+    }
+
+    /// Window title with what's in use, e.g. "WhatsApp — Microphone and
+    /// camera in use"
+    pub fn title(&self, base: &str) -> String {
+        if !self.is_active() {
+            return base.to_string();
+        }
+
+        let devices: Vec<String> = [
+            ("microphone", self.microphone),
+            ("camera", self.camera),
+            ("screen", self.screen),
+        ]
+        .iter()
+        .filter_map(|(name, capture)| match capture {
+            Capture::Off => None,
+            Capture::Active => Some(name.to_string()),
+            Capture::Muted => Some(format!("{} (muted)", name)),
+        })
+        .collect();
+
+        let list = match devices.as_slice() {
+            [rest @ .., last] if !rest.is_empty() => {
+                format!("{} and {}", rest.join(", "), last)
+            }
+            _ => devices.concat(),
+        };
+
+        let mut chars = list.chars();
+        let list = match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => list,
+        };
+        format!("{} — {} in use", base, list)
+    }
+
+    /// Script updating the overlay badge
+    pub fn badge_script(&self) -> String {
+        format!(
+            "window.__captureBadge && window.__captureBadge.update({});",
+            serde_json::to_string(self).unwrap_or_else(|_| "{}".to_string())
+        )
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use watch::{stop_all, watch};
+
+#[cfg(target_os = "linux")]
+mod watch {
+    use super::{Capture, CaptureState};
+    use crate::UserEvent;
+    use tao::event_loop::EventLoopProxy;
+    use webkit2gtk::{MediaCaptureState, WebViewExt};
+
+    fn capture(state: MediaCaptureState) -> Capture {
+        match state {
+            MediaCaptureState::Active => Capture::Active,
+            MediaCaptureState::Muted => Capture::Muted,
+            _ => Capture::Off,
+        }
+    }
+
+    fn current(webview: &webkit2gtk::WebView) -> CaptureState {
+        CaptureState {
+            microphone: capture(webview.microphone_capture_state()),
+            camera: capture(webview.camera_capture_state()),
+            screen: capture(webview.display_capture_state()),
+        }
+    }
+
+    /// Send a `CaptureChanged` event whenever a capture state changes
+    pub fn watch(webview: &webkit2gtk::WebView, proxy: EventLoopProxy<UserEvent>) {
+        let send = move |webview: &webkit2gtk::WebView| {
+            let state = current(webview);
+            eprintln!("[CAPTURE] {:?}", state);
+            let _ = proxy.send_event(UserEvent::CaptureChanged(state));
+        };
+
+        let on_microphone = send.clone();
+        webview.connect_microphone_capture_state_notify(move |webview| on_microphone(webview));
+        let on_camera = send.clone();
+        webview.connect_camera_capture_state_notify(move |webview| on_camera(webview));
+        webview.connect_display_capture_state_notify(move |webview| send(webview));
+    }
+
+    /// Stop every capture the page has running
+    pub fn stop_all(webview: &webkit2gtk::WebView) {
+        if !current(webview).is_active() {
+            return;
+        }
+        eprintln!("[CAPTURE] Stopping all capture");
+        webview.set_microphone_capture_state(MediaCaptureState::None);
+        webview.set_camera_capture_state(MediaCaptureState::None);
+        webview.set_display_capture_state(MediaCaptureState::None);
+    }
+}
+
+/// Injected into every page: the in-use badge, updated from Rust
+pub const CAPTURE_BADGE_SCRIPT: &str = r#"
+(function() {
+    'use strict';
+
+    const send = (message) => window.ipc.postMessage(JSON.stringify(message));
+
+    function update(state) {
+        let badge = document.getElementById('capture-badge');
+        const devices = [];
+        if (state.microphone !== 'Off') devices.push({ name: 'Mic', muted: state.microphone === 'Muted' });
+        if (state.camera !== 'Off') devices.push({ name: 'Camera', muted: state.camera === 'Muted' });
+        if (state.screen !== 'Off') devices.push({ name: 'Screen', muted: state.screen === 'Muted' });
+
+        if (devices.length === 0) {
+            if (badge) badge.remove();
+            return;
+        }
+
+        if (!badge) {
+            badge = document.createElement('div');
+            badge.id = 'capture-badge';
+            badge.title = 'Click or press Ctrl+Shift+K to stop all capture';
+            badge.style.cssText = 'position:fixed;top:10px;right:10px;z-index:1000003;background:#d93025;color:white;padding:5px 12px;border-radius:14px;font-size:12px;font-weight:bold;cursor:pointer;box-shadow:0 2px 10px rgba(0,0,0,0.3);user-select:none;';
+            badge.onclick = () => send('StopCapture');
+            (document.body || document.documentElement).appendChild(badge);
+        }
+
+        const allMuted = devices.every(d => d.muted);
+        badge.style.background = allMuted ? '#54656f' : '#d93025';
+        badge.textContent = '● ' + devices.map(d => d.muted ? d.name + ' (muted)' : d.name).join(' · ') + ' ✕';
+    }
+
+    window.__captureBadge = { update };
+})();
+"#;
@@ -0,0 +1,296 @@
+//! Deduplication of downloads by content hash
+//!
+//! Forwarded memes and PDFs are downloaded again and again. Each finished file
+//! is hashed; if the history already has a file with identical contents, the
+//! new copy is deleted and the download points at the existing file
+//! ([`DedupMode::Reference`]), or the new name becomes a hardlink to it
+//! ([`DedupMode::Hardlink`]). Hash matches are always confirmed byte by byte.
+
+use crate::downloads;
+use crate::history::History;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const BUFFER_LEN: usize = 64 * 1024;
+
+/// What happens to a download whose contents were downloaded before
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DedupMode {
+    /// Keep every copy
+    Off,
+    /// Delete the new copy and use the existing file
+    #[default]
+    Reference,
+    /// Replace the new copy with a hardlink to the existing file
+    Hardlink,
+}
+
+/// 64-bit FNV-1a hash of a file's contents, as hex.
+///
+/// Not collision resistant, which is fine: matches are verified with
+/// [`same_contents`] before anything is deleted.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    let mut file = File::open(path)?;
+    let mut buf = vec![0u8; BUFFER_LEN];
+    let mut hash = OFFSET_BASIS;
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        for &byte in &buf[..n] {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+
+    Ok(format!("{:016x}", hash))
+}
+
+/// Whether two files have identical contents
+pub fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    let (meta_a, meta_b) = (fs::metadata(a)?, fs::metadata(b)?);
+    if meta_a.len() != meta_b.len() {
+        return Ok(false);
+    }
+
+    let mut a = BufReader::with_capacity(BUFFER_LEN, File::open(a)?);
+    let mut b = BufReader::with_capacity(BUFFER_LEN, File::open(b)?);
+    let mut buf_a = vec![0u8; BUFFER_LEN];
+    let mut buf_b = vec![0u8; BUFFER_LEN];
+
+    loop {
+        let n = a.read(&mut buf_a)?;
+        if n == 0 {
+            // Same length, so b is at its end too
+            return Ok(true);
+        }
+        b.read_exact(&mut buf_b[..n])?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Look for an earlier download with the same contents as `path` and apply
+/// `mode`. Returns the path to use from now on and the history id of the
+/// original, if `path` was a duplicate.
+pub fn deduplicate(
+    path: &Path,
+    hash: &str,
+    mode: DedupMode,
+    history: &Mutex<History>,
+) -> (PathBuf, Option<u64>) {
+    let unchanged = (path.to_path_buf(), None);
+    if mode == DedupMode::Off {
+        return unchanged;
+    }
+
+    // Collect candidates first; comparing files can take a while
+    let candidates: Vec<(u64, PathBuf)> = match history.lock() {
+        Ok(history) => history
+            .entries
+            .iter()
+            .rev()
+            .filter(|e| e.hash.as_deref() == Some(hash) && e.path != path)
+            .map(|e| (e.id, e.path.clone()))
+            .collect(),
+        Err(_) => return unchanged,
+    };
+
+    let Some((id, original)) = candidates
+        .into_iter()
+        .find(|(_, original)| same_contents(path, original).unwrap_or(false))
+    else {
+        return unchanged;
+    };
+
+    let result = match mode {
+        DedupMode::Reference => fs::remove_file(path).map(|()| original.clone()),
+        DedupMode::Hardlink => hardlink_over(&original, path).map(|()| path.to_path_buf()),
+        DedupMode::Off => return unchanged,
+    };
+
+    match result {
+        Ok(path) => {
+            eprintln!("[DEDUP] Same contents as {:?} (#{})", original, id);
+            (path, Some(id))
+        }
+        Err(e) => {
+            eprintln!("[DEDUP] Failed to deduplicate {:?}: {}", path, e);
+            unchanged
+        }
+    }
+}
+
+/// Atomically replace `path` with a hardlink to `original`
+fn hardlink_over(original: &Path, path: &Path) -> io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    // Link next to the target first so a failure leaves the download intact
+    let temp = downloads::unique_path(dir, &format!(".{}.link", name), |_| false);
+    fs::hard_link(original, &temp)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postprocess::{AfterDownload, DownloadReport};
+
+    /// A fresh directory for one test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("whatsapp-dedup-test-{}", std::process::id()))
+            .join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write a download and add it to the history, as the pipeline would
+    fn download(history: &Mutex<History>, path: &Path, contents: &[u8]) -> u64 {
+        fs::write(path, contents).unwrap();
+        history.lock().unwrap().record(&DownloadReport {
+            url: format!("https://example.com/{}", path.display()),
+            path: path.to_path_buf(),
+            after: AfterDownload::Nothing,
+            action: None,
+            verdict: None,
+            hash: Some(hash_file(path).unwrap()),
+            duplicate_of: None,
+        })
+    }
+
+    #[test]
+    fn hashes_contents() {
+        let dir = temp_dir("hash");
+        let (a, b, c, empty) = (dir.join("a"), dir.join("b"), dir.join("c"), dir.join("e"));
+        fs::write(&a, "meme").unwrap();
+        fs::write(&b, "meme").unwrap();
+        fs::write(&c, "mime").unwrap();
+        fs::write(&empty, "").unwrap();
+
+        assert_eq!(hash_file(&a).unwrap(), hash_file(&b).unwrap());
+        assert_ne!(hash_file(&a).unwrap(), hash_file(&c).unwrap());
+        // FNV-1a offset basis
+        assert_eq!(hash_file(&empty).unwrap(), "cbf29ce484222325");
+        assert!(hash_file(&dir.join("missing")).is_err());
+    }
+
+    #[test]
+    fn compares_contents() {
+        let dir = temp_dir("compare");
+        // Longer than one buffer, differing only in the last byte
+        let mut contents = vec![7u8; BUFFER_LEN * 2 + 10];
+        let (a, b, c, short) = (dir.join("a"), dir.join("b"), dir.join("c"), dir.join("s"));
+        fs::write(&a, &contents).unwrap();
+        fs::write(&b, &contents).unwrap();
+        *contents.last_mut().unwrap() = 8;
+        fs::write(&c, &contents).unwrap();
+        fs::write(&short, &contents[1..]).unwrap();
+
+        assert!(same_contents(&a, &b).unwrap());
+        assert!(!same_contents(&a, &c).unwrap());
+        assert!(!same_contents(&a, &short).unwrap());
+        assert!(same_contents(&a, &dir.join("missing")).is_err());
+    }
+
+    #[test]
+    fn reference_removes_the_new_copy() {
+        let dir = temp_dir("reference");
+        let history = Mutex::new(History::default());
+        let (original, copy) = (dir.join("meme.jpg"), dir.join("meme (1).jpg"));
+        let id = download(&history, &original, b"same picture");
+        fs::write(&copy, b"same picture").unwrap();
+
+        let hash = hash_file(&copy).unwrap();
+        let (path, duplicate_of) = deduplicate(&copy, &hash, DedupMode::Reference, &history);
+        assert_eq!((path, duplicate_of), (original.clone(), Some(id)));
+        assert!(!copy.exists());
+        assert_eq!(fs::read(&original).unwrap(), b"same picture");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hardlink_links_the_new_name() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = temp_dir("hardlink");
+        let history = Mutex::new(History::default());
+        let (original, copy) = (dir.join("doc.pdf"), dir.join("doc (1).pdf"));
+        let id = download(&history, &original, b"%PDF-1.7 same");
+        fs::write(&copy, b"%PDF-1.7 same").unwrap();
+
+        let hash = hash_file(&copy).unwrap();
+        let (path, duplicate_of) = deduplicate(&copy, &hash, DedupMode::Hardlink, &history);
+        assert_eq!((path, duplicate_of), (copy.clone(), Some(id)));
+        let (a, b) = (
+            fs::metadata(&original).unwrap(),
+            fs::metadata(&copy).unwrap(),
+        );
+        assert_eq!((a.dev(), a.ino()), (b.dev(), b.ino()));
+        // No temporary link left over
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn keeps_files_that_only_share_a_hash_or_size() {
+        let dir = temp_dir("different");
+        let history = Mutex::new(History::default());
+        let (original, other) = (dir.join("a.txt"), dir.join("b.txt"));
+        download(&history, &original, b"first file");
+        fs::write(&other, b"other file").unwrap();
+
+        // Pretend the hashes collided; the byte comparison catches it
+        let hash = history.lock().unwrap().entries[0].hash.clone().unwrap();
+        for mode in [DedupMode::Reference, DedupMode::Hardlink] {
+            assert_eq!(
+                deduplicate(&other, &hash, mode, &history),
+                (other.clone(), None)
+            );
+        }
+        assert_eq!(fs::read(&other).unwrap(), b"other file");
+        assert_eq!(fs::read(&original).unwrap(), b"first file");
+    }
+
+    #[test]
+    fn off_keeps_every_copy() {
+        let dir = temp_dir("off");
+        let history = Mutex::new(History::default());
+        let (original, copy) = (dir.join("a.txt"), dir.join("b.txt"));
+        download(&history, &original, b"same");
+        fs::write(&copy, b"same").unwrap();
+
+        let hash = hash_file(&copy).unwrap();
+        assert_eq!(
+            deduplicate(&copy, &hash, DedupMode::Off, &history),
+            (copy.clone(), None)
+        );
+        assert!(copy.exists());
+    }
+
+    #[test]
+    fn failed_hardlink_leaves_the_download() {
+        let dir = temp_dir("failed-link");
+        let path = dir.join("download.bin");
+        fs::write(&path, b"downloaded").unwrap();
+
+        assert!(hardlink_over(&dir.join("gone.bin"), &path).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"downloaded");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
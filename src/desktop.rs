@@ -0,0 +1,104 @@
+//! Desktop integration: file manager and notifications
+
+use std::path::Path;
+use std::process::Command;
+
+/// Ask the file manager to show a file, selected, in its folder.
+///
+/// Uses the org.freedesktop.FileManager1 `ShowItems` D-Bus call (Nautilus,
+/// Dolphin, Nemo, Thunar, ...) and falls back to opening the parent folder.
+pub fn reveal_in_folder(path: &Path) {
+    eprintln!("[SYSTEM] Revealing: {:?}", path);
+
+    #[cfg(target_os = "linux")]
+    match show_items(path) {
+        Ok(()) => return,
+        Err(e) => eprintln!("[SYSTEM] FileManager1.ShowItems failed: {}", e),
+    }
+
+    if let Some(parent) = path.parent() {
+        crate::open_with_system(&parent.to_path_buf());
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn show_items(path: &Path) -> Result<(), gio::glib::Error> {
+    use gio::glib::ToVariant;
+
+    let uri = gio::glib::filename_to_uri(path, None)?;
+    let connection = gio::bus_get_sync(gio::BusType::Session, None::<&gio::Cancellable>)?;
+
+    // ShowItems(as uris, s startup_id)
+    let params = (vec![uri.to_string()], "").to_variant();
+    connection.call_sync(
+        Some("org.freedesktop.FileManager1"),
+        "/org/freedesktop/FileManager1",
+        "org.freedesktop.FileManager1",
+        "ShowItems",
+        Some(&params),
+        None,
+        gio::DBusCallFlags::NONE,
+        5000,
+        None::<&gio::Cancellable>,
+    )?;
+
+    Ok(())
+}
+
+/// Button the user clicked on a download notification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationAction {
+    Open,
+    Show,
+}
+
+/// Show a "download finished" notification with Open / Show buttons.
+///
+/// Blocks until the notification is clicked, dismissed or expires, so call it
+/// from a thread of its own.
+pub fn notify_download(path: &Path) -> Option<NotificationAction> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let output = Command::new("notify-send")
+        .args([
+            "--app-name=WhatsApp",
+            "--icon=whatsapp",
+            "--action=open=Open",
+            "--action=show=Show in Folder",
+            // A file name starting with '-' is not an option
+            "--",
+            "Download finished",
+            &name,
+        ])
+        .output();
+
+    match output {
+        Ok(output) if output.status.success() => {
+            match String::from_utf8_lossy(&output.stdout).trim() {
+                "open" => Some(NotificationAction::Open),
+                "show" => Some(NotificationAction::Show),
+                _ => None,
+            }
+        }
+        // notify-send older than 0.7.10 has no --action; notify without buttons
+        Ok(_) => {
+            let _ = Command::new("notify-send")
+                .args([
+                    "--app-name=WhatsApp",
+                    "--icon=whatsapp",
+                    "--",
+                    "Download finished",
+                    &name,
+                ])
+                .spawn();
+            None
+        }
+        Err(e) => {
+            eprintln!("[NOTIFY] notify-send unavailable: {}", e);
+            None
+        }
+    }
+}
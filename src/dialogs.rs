@@ -0,0 +1,360 @@
+//! "Open with" chooser dialogs
+//!
+//! Choosers are requested from the download worker threads but GTK widgets
+//! may only be touched on the main thread. [`Chooser::choose`] therefore sends
+//! a [`ChooserRequest`] to the event loop, which shows a native dialog parented
+//! to the main window, and blocks until the user answered. The old zenity /
+//! kdialog subprocess path is kept as an opt-in fallback.
+
+use crate::UserEvent;
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use std::sync::mpsc::{self, Sender};
+use tao::event_loop::EventLoopProxy;
+
+/// Which implementation shows chooser dialogs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DialogBackend {
+    /// In-process GTK dialog
+    #[default]
+    Native,
+    /// zenity, falling back to kdialog
+    Subprocess,
+}
+
+/// The option the user picked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Choice {
+    pub index: usize,
+    /// "Remember this choice" was ticked
+    pub remember: bool,
+}
+
+/// A chooser to show on the main thread; the answer goes to `reply`
+#[derive(Debug)]
+pub struct ChooserRequest {
+    pub title: String,
+    pub text: String,
+    pub options: Vec<String>,
+    /// Label of the button that confirms the selection
+    pub accept_label: String,
+    /// Offer the "Remember this choice" checkbox
+    pub rememberable: bool,
+    pub reply: Sender<Option<Choice>>,
+}
+
+/// Handle for showing choosers from any thread
+#[derive(Clone)]
+pub struct Chooser {
+    proxy: EventLoopProxy<UserEvent>,
+    backend: DialogBackend,
+}
+
+impl Chooser {
+    pub fn new(proxy: EventLoopProxy<UserEvent>, backend: DialogBackend) -> Self {
+        Self { proxy, backend }
+    }
+
+    /// Ask the user to pick one of `options`; blocks until answered.
+    /// Must not be called from the main thread with the native backend.
+    pub fn choose(&self, title: &str, text: &str, options: &[&str]) -> Option<Choice> {
+        self.ask(title, text, options, "Open", true)
+    }
+
+    /// Like [`Chooser::choose`], with another label on the accept button
+    pub fn choose_with(
+        &self,
+        title: &str,
+        text: &str,
+        options: &[&str],
+        accept_label: &str,
+    ) -> Option<Choice> {
+        self.ask(title, text, options, accept_label, true)
+    }
+
+    /// Like [`Chooser::choose`], for one-off questions that can't be
+    /// remembered; returns the index of the picked option
+    pub fn confirm(
+        &self,
+        title: &str,
+        text: &str,
+        options: &[&str],
+        accept_label: &str,
+    ) -> Option<usize> {
+        self.ask(title, text, options, accept_label, false)
+            .map(|choice| choice.index)
+    }
+
+    fn ask(
+        &self,
+        title: &str,
+        text: &str,
+        options: &[&str],
+        accept_label: &str,
+        rememberable: bool,
+    ) -> Option<Choice> {
+        if self.backend == DialogBackend::Subprocess {
+            #[cfg(target_os = "linux")]
+            return show_subprocess_dialog(title, text, options, rememberable);
+        }
+
+        let (reply, answer) = mpsc::channel();
+        let request = ChooserRequest {
+            title: title.to_string(),
+            text: text.to_string(),
+            options: options.iter().map(|o| o.to_string()).collect(),
+            accept_label: accept_label.to_string(),
+            rememberable,
+            reply,
+        };
+
+        if self
+            .proxy
+            .send_event(UserEvent::ShowChooser(request))
+            .is_err()
+        {
+            eprintln!("[DIALOG] Event loop closed, cannot show {:?}", title);
+            return None;
+        }
+
+        answer.recv().ok().flatten()
+    }
+}
+
+/// Show a chooser with one radio button per option and a "remember" checkbox
+#[cfg(target_os = "linux")]
+pub fn show_native_dialog(parent: &gtk::ApplicationWindow, request: ChooserRequest) {
+    use gtk::prelude::*;
+    use std::cell::RefCell;
+
+    let dialog = gtk::Dialog::with_buttons(
+        Some(&request.title),
+        Some(parent),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        &[
+            ("Cancel", gtk::ResponseType::Cancel),
+            (request.accept_label.as_str(), gtk::ResponseType::Accept),
+        ],
+    );
+    dialog.set_default_response(gtk::ResponseType::Accept);
+
+    let content = dialog.content_area();
+    content.set_spacing(6);
+    content.set_border_width(12);
+
+    let label = gtk::Label::new(Some(&request.text));
+    label.set_xalign(0.0);
+    content.pack_start(&label, false, false, 0);
+
+    let mut radios: Vec<gtk::RadioButton> = Vec::new();
+    for option in &request.options {
+        let radio = match radios.first() {
+            Some(first) => gtk::RadioButton::with_label_from_widget(first, option),
+            None => gtk::RadioButton::with_label(option),
+        };
+        content.pack_start(&radio, false, false, 0);
+        radios.push(radio);
+    }
+
+    let remember = gtk::CheckButton::with_label("Remember this choice");
+    if request.rememberable {
+        content.pack_start(&remember, false, false, 6);
+    }
+
+    // The response handler may run more than once (e.g. Open, then the
+    // delete-event of the destroyed window); only the first answer counts
+    let reply = RefCell::new(Some(request.reply));
+
+    dialog.connect_response(move |dialog, response| {
+        let Some(reply) = reply.borrow_mut().take() else {
+            return;
+        };
+
+        let choice = if response == gtk::ResponseType::Accept {
+            radios
+                .iter()
+                .position(|radio| radio.is_active())
+                .map(|index| Choice {
+                    index,
+                    remember: remember.is_active(),
+                })
+        } else {
+            None
+        };
+
+        let _ = reply.send(choice);
+        unsafe { dialog.destroy() };
+    });
+
+    dialog.show_all();
+}
+
+/// Show a dialog to let user pick from options using zenity, or kdialog
+#[cfg(target_os = "linux")]
+fn show_subprocess_dialog(
+    title: &str,
+    text: &str,
+    options: &[&str],
+    rememberable: bool,
+) -> Option<Choice> {
+    // Try zenity first
+    let result = Command::new("zenity")
+        .args([
+            "--list",
+            "--radiolist",
+            "--title",
+            title,
+            "--text",
+            text,
+            "--column",
+            "Select",
+            "--column",
+            "Option",
+        ])
+        .args(
+            options
+                .iter()
+                .enumerate()
+                .flat_map(|(i, opt)| {
+                    if i == 0 {
+                        vec!["TRUE", *opt]
+                    } else {
+                        vec!["FALSE", *opt]
+                    }
+                })
+                .collect::<Vec<_>>(),
+        )
+        .output();
+
+    if let Ok(output) = result {
+        if output.status.success() {
+            let selected = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            let idx = options.iter().position(|&o| o == selected)?;
+            let remember = rememberable
+                && Command::new("zenity")
+                    .args([
+                        "--question",
+                        "--title",
+                        title,
+                        "--text",
+                        &format!("Remember this choice ({})?", selected),
+                        "--ok-label",
+                        "Always",
+                        "--cancel-label",
+                        "Just this once",
+                    ])
+                    .status()
+                    .map(|s| s.success())
+                    .unwrap_or(false);
+            return Some(Choice {
+                index: idx,
+                remember,
+            });
+        }
+    }
+
+    // Fallback to kdialog
+    let result = Command::new("kdialog")
+        .args(["--menu", text])
+        .args(
+            options
+                .iter()
+                .flat_map(|opt| [opt, opt])
+                .collect::<Vec<_>>(),
+        )
+        .output();
+
+    if let Ok(output) = result {
+        if output.status.success() {
+            let selected = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            let idx = options.iter().position(|&o| o == selected)?;
+            let remember = rememberable
+                && Command::new("kdialog")
+                    .args([
+                        "--yesno",
+                        &format!("Remember this choice ({})?", selected),
+                        "--yes-label",
+                        "Always",
+                        "--no-label",
+                        "Just this once",
+                    ])
+                    .status()
+                    .map(|s| s.success())
+                    .unwrap_or(false);
+            return Some(Choice {
+                index: idx,
+                remember,
+            });
+        }
+    }
+
+    eprintln!("[DIALOG] Neither zenity nor kdialog could show the chooser");
+    None
+}
+
+/// Ask for a passphrase before the main window exists; blocks until
+/// answered. With `confirm` a new passphrase is created and has to be typed
+/// twice; otherwise it unlocks. `None` if cancelled.
+#[cfg(target_os = "linux")]
+pub fn ask_passphrase(title: &str, text: &str, confirm: bool) -> Option<String> {
+    use gtk::prelude::*;
+
+    let accept = if confirm { "Create" } else { "Unlock" };
+    let dialog = gtk::Dialog::with_buttons(
+        Some(title),
+        None::<&gtk::Window>,
+        gtk::DialogFlags::MODAL,
+        &[
+            ("Cancel", gtk::ResponseType::Cancel),
+            (accept, gtk::ResponseType::Accept),
+        ],
+    );
+    dialog.set_default_response(gtk::ResponseType::Accept);
+
+    let content = dialog.content_area();
+    content.set_spacing(6);
+    content.set_border_width(12);
+
+    let label = gtk::Label::new(Some(text));
+    label.set_xalign(0.0);
+    content.pack_start(&label, false, false, 0);
+
+    let passphrase_entry = || {
+        let entry = gtk::Entry::new();
+        entry.set_visibility(false);
+        entry.set_activates_default(true);
+        entry
+    };
+    let entry = passphrase_entry();
+    content.pack_start(&entry, false, false, 0);
+
+    let repeat = passphrase_entry();
+    repeat.set_placeholder_text(Some("Repeat passphrase"));
+    if confirm {
+        content.pack_start(&repeat, false, false, 0);
+    }
+
+    let error = gtk::Label::new(None);
+    error.set_xalign(0.0);
+    content.pack_start(&error, false, false, 0);
+
+    dialog.show_all();
+
+    let passphrase = loop {
+        if dialog.run() != gtk::ResponseType::Accept {
+            break None;
+        }
+
+        let passphrase = entry.text().to_string();
+        if passphrase.is_empty() {
+            error.set_text("The passphrase can't be empty");
+        } else if confirm && repeat.text() != passphrase {
+            error.set_text("The passphrases don't match");
+        } else {
+            break Some(passphrase);
+        }
+    };
+
+    unsafe { dialog.destroy() };
+    passphrase
+}
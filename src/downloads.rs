@@ -0,0 +1,774 @@
+//! Download file naming and location
+//!
+//! Picks the name a download is saved under: the server's (Content-Disposition)
+//! or WebKit's suggested filename when it is meaningful, the URL path otherwise,
+//! and a timestamped fallback for the opaque hashes WhatsApp media URLs carry.
+//! Existing files are never overwritten; a ` (1)`, ` (2)`, ... suffix is added.
+//!
+//! The download root comes from the config, `XDG_DOWNLOAD_DIR` or
+//! `user-dirs.dirs`, and finished files can be sorted into templated
+//! subfolders such as `{type}/{yyyy-mm}`. Downloads that are too large, or
+//! would fill up the disk, are refused up front.
+
+use crate::sniff::Category;
+use percent_encoding::percent_decode_str;
+use std::path::{Component, Path, PathBuf};
+
+/// Longest filename (in bytes) most Linux filesystems accept
+const MAX_FILENAME_LEN: usize = 255;
+
+/// Make a suggested filename safe to use inside the downloads directory
+pub fn sanitize_filename(name: &str) -> Option<String> {
+    // Only the last path component, whatever separator was used
+    let name = name.rsplit(['/', '\\']).next().unwrap_or("");
+
+    let cleaned: String = name
+        .chars()
+        .map(|c| if c.is_control() { '_' } else { c })
+        .collect();
+
+    // No hidden files, no "..", no surrounding whitespace
+    let cleaned = cleaned.trim().trim_start_matches('.').trim();
+    if cleaned.is_empty() {
+        return None;
+    }
+
+    Some(truncate_filename(cleaned, MAX_FILENAME_LEN))
+}
+
+/// Shorten a filename to `max` bytes, keeping its extension
+fn truncate_filename(name: &str, max: usize) -> String {
+    if name.len() <= max {
+        return name.to_string();
+    }
+
+    let (stem, ext) = split_extension(name);
+    let ext_len = ext.map(|e| e.len() + 1).unwrap_or(0);
+    let mut stem_len = max.saturating_sub(ext_len);
+    while !stem.is_char_boundary(stem_len) {
+        stem_len -= 1;
+    }
+
+    match ext {
+        Some(ext) => format!("{}.{}", &stem[..stem_len], ext),
+        None => stem[..stem_len].to_string(),
+    }
+}
+
+/// Split "name.ext" into ("name", Some("ext")); dotfiles have no extension
+fn split_extension(name: &str) -> (&str, Option<&str>) {
+    match name.rfind('.') {
+        Some(idx) if idx > 0 && idx + 1 < name.len() => (&name[..idx], Some(&name[idx + 1..])),
+        _ => (name, None),
+    }
+}
+
+/// Last path segment of an http(s) URL, percent-decoded
+pub fn filename_from_url(url: &str) -> Option<String> {
+    // blob: and data: URLs never carry a filename
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+
+    let path = rest.split(['?', '#']).next().unwrap_or("");
+    let segment = path.split_once('/').map(|(_, p)| p)?.rsplit('/').next()?;
+
+    sanitize_filename(&percent_decode_str(segment).decode_utf8_lossy())
+}
+
+/// Whether a filename is a meaningless hash or id rather than a real name
+fn is_opaque_name(name: &str) -> bool {
+    let (stem, ext) = split_extension(name);
+
+    // WebKit's own placeholder when nothing better is known
+    if stem.eq_ignore_ascii_case("unknown") || stem.eq_ignore_ascii_case("download") {
+        return true;
+    }
+
+    // Encrypted WhatsApp media blobs
+    if ext.map(|e| e.eq_ignore_ascii_case("enc")).unwrap_or(false) {
+        return true;
+    }
+
+    // Blob URLs and hashes (mmg.whatsapp.net); names made of words, dates
+    // and version numbers are kept, whatever their separators
+    is_uuid(stem) || is_hex_hash(stem) || is_base64_hash(stem)
+}
+
+/// `8-4-4-4-12` hex digits
+fn is_uuid(s: &str) -> bool {
+    let groups: Vec<&str> = s.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// A hex digest of at least 64 bits
+fn is_hex_hash(s: &str) -> bool {
+    s.len() >= 16
+        && s.chars().all(|c| c.is_ascii_hexdigit())
+        && s.chars().any(|c| c.is_ascii_digit())
+}
+
+/// Base64 of at least 18 bytes: no word separators, and switching between
+/// upper case, lower case and digits far more often than CamelCase words do
+fn is_base64_hash(s: &str) -> bool {
+    let s = s.trim_end_matches('=');
+    if s.len() < 24 || !s.chars().all(|c| c.is_ascii_alphanumeric() || c == '+') {
+        return false;
+    }
+
+    let class = |c: char| {
+        if c.is_ascii_uppercase() {
+            0
+        } else if c.is_ascii_lowercase() {
+            1
+        } else {
+            2
+        }
+    };
+    let switches = s
+        .chars()
+        .zip(s.chars().skip(1))
+        .filter(|&(a, b)| class(a) != class(b))
+        .count();
+    switches * 3 >= s.len()
+}
+
+/// Pick a filename from WebKit's suggestion or the URL.
+///
+/// Returns `None` when neither is meaningful, in which case the caller falls
+/// back to [`fallback_filename`] and the extension is sniffed after completion.
+pub fn choose_filename(suggested: Option<&str>, url: &str) -> Option<String> {
+    suggested
+        .and_then(sanitize_filename)
+        .filter(|name| !is_opaque_name(name))
+        .or_else(|| filename_from_url(url).filter(|name| !is_opaque_name(name)))
+}
+
+/// WhatsApp-style name for downloads without a usable filename
+pub fn fallback_filename() -> String {
+    #[cfg(target_os = "linux")]
+    let timestamp = glib::DateTime::now_local()
+        .ok()
+        .and_then(|now| now.format("%Y-%m-%d at %H-%M-%S").ok())
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| "file".to_string());
+
+    #[cfg(not(target_os = "linux"))]
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs().to_string())
+        .unwrap_or_else(|_| "file".to_string());
+
+    format!("WhatsApp Download {}", timestamp)
+}
+
+/// First path in `dir` for `filename` that neither exists nor is `taken`,
+/// numbered "name (1).ext", "name (2).ext", ... as needed
+pub fn unique_path(dir: &Path, filename: &str, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    let candidate = dir.join(filename);
+    if !candidate.exists() && !taken(&candidate) {
+        return candidate;
+    }
+
+    let (stem, ext) = split_extension(filename);
+    (1..)
+        .map(|n| match ext {
+            Some(ext) => dir.join(format!("{} ({}).{}", stem, n, ext)),
+            None => dir.join(format!("{} ({})", stem, n)),
+        })
+        .find(|path| !path.exists() && !taken(path))
+        .unwrap_or(candidate)
+}
+
+/// Expand a leading `~` or `$HOME` to the home directory
+pub fn expand_home(path: &str, home: &Path) -> PathBuf {
+    for prefix in ["~", "$HOME", "${HOME}"] {
+        if let Some(rest) = path.strip_prefix(prefix) {
+            if rest.is_empty() {
+                return home.to_path_buf();
+            }
+            if let Some(rest) = rest.strip_prefix('/') {
+                return home.join(rest);
+            }
+        }
+    }
+
+    PathBuf::from(path)
+}
+
+/// Find `XDG_DOWNLOAD_DIR` in the contents of `user-dirs.dirs`
+pub fn parse_user_dirs(content: &str, home: &Path) -> Option<PathBuf> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .find(|(key, _)| key.trim() == "XDG_DOWNLOAD_DIR")
+        .map(|(_, value)| expand_home(value.trim().trim_matches('"'), home))
+        .filter(|path| path.is_absolute())
+}
+
+/// Resolve the directory downloads are saved to.
+///
+/// Order: the configured directory, `$XDG_DOWNLOAD_DIR`, `user-dirs.dirs`,
+/// `~/Downloads`, and the temp directory if there is no home at all.
+pub fn resolve_download_dir(configured: Option<&str>) -> PathBuf {
+    let Some(home) = dirs::home_dir() else {
+        return configured
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .unwrap_or_else(std::env::temp_dir);
+    };
+
+    if let Some(dir) = configured
+        .map(|c| expand_home(c.trim(), &home))
+        .filter(|p| p.is_absolute())
+    {
+        return dir;
+    }
+
+    if let Some(dir) = std::env::var("XDG_DOWNLOAD_DIR")
+        .ok()
+        .map(|v| expand_home(&v, &home))
+        .filter(|p| p.is_absolute())
+    {
+        return dir;
+    }
+
+    let user_dirs = dirs::config_dir()
+        .unwrap_or_else(|| home.join(".config"))
+        .join("user-dirs.dirs");
+    if let Some(dir) = std::fs::read_to_string(user_dirs)
+        .ok()
+        .and_then(|content| parse_user_dirs(&content, &home))
+    {
+        return dir;
+    }
+
+    home.join("Downloads")
+}
+
+/// Folder name used for `{type}` in subfolder templates
+fn category_folder(category: Category) -> &'static str {
+    match category {
+        Category::Image => "Images",
+        Category::Video => "Videos",
+        Category::Audio => "Audio",
+        Category::Document => "Documents",
+        Category::Archive => "Archives",
+        Category::Other => "Other",
+    }
+}
+
+/// Expand a subfolder template such as `{type}/{yyyy-mm}`.
+///
+/// Supported placeholders: `{type}`, `{yyyy}`, `{mm}`, `{dd}`, `{yyyy-mm}`.
+/// The result is always relative; `..` and absolute parts are dropped.
+pub fn expand_subfolder(
+    template: &str,
+    category: Category,
+    (year, month, day): (i32, u32, u32),
+) -> PathBuf {
+    let expanded = template
+        .replace("{type}", category_folder(category))
+        .replace("{yyyy-mm}", &format!("{:04}-{:02}", year, month))
+        .replace("{yyyy}", &format!("{:04}", year))
+        .replace("{mm}", &format!("{:02}", month))
+        .replace("{dd}", &format!("{:02}", day));
+
+    Path::new(&expanded)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Today's date as (year, month, day), local time where available
+pub fn today() -> (i32, u32, u32) {
+    #[cfg(target_os = "linux")]
+    if let Ok(now) = glib::DateTime::now_local() {
+        return (now.year(), now.month() as u32, now.day_of_month() as u32);
+    }
+
+    let days = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86_400)
+        .unwrap_or(0) as i64;
+    civil_from_days(days)
+}
+
+/// Convert days since 1970-01-01 to a UTC calendar date
+fn civil_from_days(days: i64) -> (i32, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year as i32, month, day)
+}
+
+/// Why a download was refused before or while it ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// Larger than the configured maximum
+    TooLarge { size: u64, max: u64 },
+    /// Would leave less than the configured reserve free
+    NoSpace { needed: u64, free: u64 },
+}
+
+impl Rejection {
+    /// In-app message for a rejected download
+    pub fn message(&self, filename: &str) -> String {
+        match self {
+            Rejection::TooLarge { size, max } => format!(
+                "Download of {} refused: {} is over the {} limit",
+                filename,
+                format_size(*size),
+                format_size(*max)
+            ),
+            Rejection::NoSpace { needed, free } => format!(
+                "Download of {} refused: needs {}, only {} free",
+                filename,
+                format_size(*needed),
+                format_size(*free)
+            ),
+        }
+    }
+}
+
+/// Check a download of `size` bytes (if known) against the size limit and
+/// the space left on the target filesystem (if known), keeping `reserve`
+/// bytes free
+pub fn check_limits(
+    size: Option<u64>,
+    free: Option<u64>,
+    max: Option<u64>,
+    reserve: u64,
+) -> Result<(), Rejection> {
+    if let (Some(size), Some(max)) = (size, max) {
+        if size > max {
+            return Err(Rejection::TooLarge { size, max });
+        }
+    }
+
+    if let Some(free) = free {
+        let needed = size.unwrap_or(0).saturating_add(reserve);
+        if needed > free {
+            return Err(Rejection::NoSpace { needed, free });
+        }
+    }
+
+    Ok(())
+}
+
+/// Free space on the filesystem holding `dir`, in bytes
+pub fn free_space(dir: &Path) -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        use gio::prelude::*;
+
+        let info = gio::File::for_path(dir)
+            .query_filesystem_info(
+                gio::FILE_ATTRIBUTE_FILESYSTEM_FREE,
+                None::<&gio::Cancellable>,
+            )
+            .ok()?;
+        if info.has_attribute(gio::FILE_ATTRIBUTE_FILESYSTEM_FREE) {
+            return Some(info.attribute_uint64(gio::FILE_ATTRIBUTE_FILESYSTEM_FREE));
+        }
+    }
+
+    let _ = dir;
+    None
+}
+
+/// Human-readable size, e.g. "1.5 GB"
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opaque_names() {
+        let cases = [
+            // Real names
+            ("Quarterly-Report-2024-Q1-draft.pdf", false),
+            ("Invoice_2024_03_12_final_v2.pdf", false),
+            ("QuarterlyReportQ1Final2024.pdf", false),
+            ("IMG-20240312-WA0007.jpg", false),
+            ("2024-03-12 at 10-15-00.pdf", false),
+            ("Supercalifragilisticexpialidocious.txt", false),
+            ("deadbeefcafe.png", false),
+            ("photo.jpg", false),
+            // Placeholders
+            ("unknown", true),
+            ("download.bin", true),
+            // Encrypted media
+            ("12345678_1234567890_12345678901234567_n.enc", true),
+            ("photo.enc", true),
+            // UUIDs
+            ("3f2b8c1e-9d4a-4b6f-8e2d-1a2b3c4d5e6f", true),
+            ("3F2B8C1E-9D4A-4B6F-8E2D-1A2B3C4D5E6F.pdf", true),
+            ("3f2b8c1e-9d4a-4b6f-8e2d.pdf", false),
+            // Hex digests
+            ("d41d8cd98f00b204e9800998ecf8427e.jpg", true),
+            ("E3B0C44298FC1C149AFBF4C8996FB924.png", true),
+            // Base64
+            ("aGVsbG8gd29ybGQgdGhpcyBpcyBhIHRlc3Q=.jpg", true),
+            ("Q2x1b3VkK3Rlc3R5+IGlkZW50aWZpZXI.mp4", true),
+            ("ARnK2pQ9xLm4Zt7Vb1Yc8Wd3Fe6Gh0J", true),
+        ];
+
+        for (name, opaque) in cases {
+            assert_eq!(is_opaque_name(name), opaque, "{}", name);
+        }
+    }
+
+    #[test]
+    fn keeps_real_suggested_names() {
+        let url = "https://mmg.whatsapp.net/v/t62/3f2b8c1e-9d4a-4b6f-8e2d-1a2b3c4d5e6f";
+        for name in [
+            "Quarterly-Report-2024-Q1-draft.pdf",
+            "Invoice_2024_03_12_final_v2.pdf",
+        ] {
+            assert_eq!(choose_filename(Some(name), url).as_deref(), Some(name));
+        }
+
+        assert_eq!(
+            choose_filename(Some("d41d8cd98f00b204e9800998ecf8427e.pdf"), url),
+            None
+        );
+        assert_eq!(
+            choose_filename(
+                None,
+                "https://example.com/files/Invoice_2024_03_12_final_v2.pdf"
+            )
+            .as_deref(),
+            Some("Invoice_2024_03_12_final_v2.pdf")
+        );
+    }
+
+    #[test]
+    fn expands_home() {
+        let home = Path::new("/home/ana");
+        let cases = [
+            ("~", "/home/ana"),
+            ("~/Downloads", "/home/ana/Downloads"),
+            ("$HOME/Downloads", "/home/ana/Downloads"),
+            ("${HOME}/Downloads", "/home/ana/Downloads"),
+            ("$HOME", "/home/ana"),
+            ("/srv/downloads", "/srv/downloads"),
+            // Only a whole leading component
+            ("~bob/Downloads", "~bob/Downloads"),
+            ("$HOMEDIR/x", "$HOMEDIR/x"),
+            ("Downloads/~", "Downloads/~"),
+        ];
+
+        for (path, expected) in cases {
+            assert_eq!(expand_home(path, home), Path::new(expected), "{}", path);
+        }
+    }
+
+    #[test]
+    fn parses_user_dirs() {
+        let home = Path::new("/home/ana");
+        let cases = [
+            (
+                "# Written by xdg-user-dirs-update\nXDG_DESKTOP_DIR=\"$HOME/Desktop\"\n\
+                 XDG_DOWNLOAD_DIR=\"$HOME/Téléchargements\"\n",
+                Some("/home/ana/Téléchargements"),
+            ),
+            ("XDG_DOWNLOAD_DIR=\"/data/dl\"", Some("/data/dl")),
+            ("  XDG_DOWNLOAD_DIR = $HOME/dl  ", Some("/home/ana/dl")),
+            ("#XDG_DOWNLOAD_DIR=\"$HOME/old\"\n", None),
+            ("XDG_DESKTOP_DIR=\"$HOME/Desktop\"\n", None),
+            // Relative paths are not allowed
+            ("XDG_DOWNLOAD_DIR=\"dl\"", None),
+            ("", None),
+        ];
+
+        for (content, expected) in cases {
+            assert_eq!(
+                parse_user_dirs(content, home).as_deref(),
+                expected.map(Path::new),
+                "{}",
+                content
+            );
+        }
+    }
+
+    /// Environment variables are shared by all tests; only this one changes
+    /// them
+    #[test]
+    fn resolves_download_dir() {
+        let Some(home) = dirs::home_dir() else {
+            return;
+        };
+        let config = std::env::temp_dir().join(format!(
+            "whatsapp-downloads-test-{}/config",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&config);
+        std::fs::create_dir_all(&config).unwrap();
+
+        let saved: Vec<_> = ["XDG_CONFIG_HOME", "XDG_DOWNLOAD_DIR"]
+            .into_iter()
+            .map(|key| (key, std::env::var_os(key)))
+            .collect();
+        std::env::set_var("XDG_CONFIG_HOME", &config);
+        std::env::remove_var("XDG_DOWNLOAD_DIR");
+
+        // No user-dirs.dirs
+        assert_eq!(resolve_download_dir(None), home.join("Downloads"));
+        assert_eq!(
+            resolve_download_dir(Some("relative")),
+            home.join("Downloads")
+        );
+
+        std::fs::write(
+            config.join("user-dirs.dirs"),
+            "XDG_DOWNLOAD_DIR=\"$HOME/From User Dirs\"\n",
+        )
+        .unwrap();
+        assert_eq!(resolve_download_dir(None), home.join("From User Dirs"));
+
+        std::env::set_var("XDG_DOWNLOAD_DIR", "$HOME/From Env");
+        assert_eq!(resolve_download_dir(None), home.join("From Env"));
+
+        // The config wins
+        assert_eq!(
+            resolve_download_dir(Some(" ~/Configured ")),
+            home.join("Configured")
+        );
+        assert_eq!(
+            resolve_download_dir(Some("/srv/downloads")),
+            Path::new("/srv/downloads")
+        );
+
+        for (key, value) in saved {
+            match value {
+                Some(value) => std::env::set_var(key, value),
+                None => std::env::remove_var(key),
+            }
+        }
+    }
+
+    #[test]
+    fn expands_subfolders() {
+        let date = (2024, 3, 7);
+        let cases = [
+            ("{type}/{yyyy-mm}", Category::Image, "Images/2024-03"),
+            ("{yyyy}/{mm}/{dd}", Category::Document, "2024/03/07"),
+            ("WhatsApp {type}", Category::Archive, "WhatsApp Archives"),
+            ("{type}", Category::Other, "Other"),
+            ("", Category::Video, ""),
+            // Unknown placeholders are kept as written
+            ("{date}/{year}", Category::Audio, "{date}/{year}"),
+            // Never outside the downloads folder
+            ("../{type}", Category::Video, "Videos"),
+            ("/etc/{yyyy}", Category::Video, "etc/2024"),
+            ("{type}/./../x", Category::Image, "Images/x"),
+        ];
+
+        for (template, category, expected) in cases {
+            assert_eq!(
+                expand_subfolder(template, category, date),
+                Path::new(expected),
+                "{}",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn converts_days_to_dates() {
+        let cases = [
+            (0, (1970, 1, 1)),
+            (-1, (1969, 12, 31)),
+            (59, (1970, 3, 1)),
+            (11_016, (2000, 2, 29)),
+            (11_017, (2000, 3, 1)),
+            (19_722, (2023, 12, 31)),
+            (19_782, (2024, 2, 29)),
+            // Century years are only leap years every 400 years
+            (-25_508, (1900, 3, 1)),
+            (47_540, (2100, 2, 28)),
+            (47_541, (2100, 3, 1)),
+        ];
+
+        for (days, expected) in cases {
+            assert_eq!(civil_from_days(days), expected, "{}", days);
+        }
+    }
+
+    #[test]
+    fn checks_size_and_space() {
+        const MB: u64 = 1024 * 1024;
+        let too_large = |size| Err(Rejection::TooLarge { size, max: 10 * MB });
+        let cases = [
+            // (size, free, max, reserve)
+            ((Some(5 * MB), None, Some(10 * MB), 0), Ok(())),
+            ((Some(10 * MB), None, Some(10 * MB), 0), Ok(())),
+            (
+                (Some(10 * MB + 1), None, Some(10 * MB), 0),
+                too_large(10 * MB + 1),
+            ),
+            ((Some(u64::MAX), None, None, 0), Ok(())),
+            // Unknown sizes can't be over the limit, but still need the reserve
+            ((None, None, Some(10 * MB), 0), Ok(())),
+            ((None, Some(MB), None, MB), Ok(())),
+            (
+                (None, Some(MB - 1), None, MB),
+                Err(Rejection::NoSpace {
+                    needed: MB,
+                    free: MB - 1,
+                }),
+            ),
+            ((Some(4 * MB), Some(5 * MB), None, MB), Ok(())),
+            (
+                (Some(4 * MB + 1), Some(5 * MB), None, MB),
+                Err(Rejection::NoSpace {
+                    needed: 5 * MB + 1,
+                    free: 5 * MB,
+                }),
+            ),
+            // The limit is checked first
+            (
+                (Some(20 * MB), Some(0), Some(10 * MB), 0),
+                too_large(20 * MB),
+            ),
+            (
+                (Some(u64::MAX), Some(MB), None, MB),
+                Err(Rejection::NoSpace {
+                    needed: u64::MAX,
+                    free: MB,
+                }),
+            ),
+        ];
+
+        for ((size, free, max, reserve), expected) in cases {
+            assert_eq!(
+                check_limits(size, free, max, reserve),
+                expected,
+                "{:?}",
+                (size, free, max, reserve)
+            );
+        }
+    }
+
+    #[test]
+    fn formats_sizes() {
+        let cases = [
+            (0, "0 B"),
+            (1023, "1023 B"),
+            (1024, "1.0 KB"),
+            (1536, "1.5 KB"),
+            (1024 * 1024, "1.0 MB"),
+            (1536 * 1024 * 1024, "1.5 GB"),
+            (1024 * 1024 * 1024 * 1024, "1.0 TB"),
+            // No unit above TB
+            (5 * 1024 * 1024 * 1024 * 1024 * 1024, "5120.0 TB"),
+        ];
+
+        for (bytes, expected) in cases {
+            assert_eq!(format_size(bytes), expected, "{}", bytes);
+        }
+    }
+
+    #[test]
+    fn sanitizes_filenames() {
+        let long = format!("{}.pdf", "a".repeat(300));
+        let long_multibyte = format!("{}.txt", "é".repeat(200));
+        let cases = [
+            ("report.pdf", Some("report.pdf".to_string())),
+            ("  report.pdf  ", Some("report.pdf".to_string())),
+            ("../../etc/passwd", Some("passwd".to_string())),
+            ("..\\..\\Windows\\evil.exe", Some("evil.exe".to_string())),
+            ("folder/", None),
+            ("..", None),
+            ("...", None),
+            (".bashrc", Some("bashrc".to_string())),
+            ("line\nbreak\t.txt", Some("line_break_.txt".to_string())),
+            ("bell\u{7}\u{0}.txt", Some("bell__.txt".to_string())),
+            ("", None),
+            ("   ", None),
+            (&long, Some(format!("{}.pdf", "a".repeat(251)))),
+            // Cut on a character boundary: 125 two-byte characters fit
+            (&long_multibyte, Some(format!("{}.txt", "é".repeat(125)))),
+        ];
+
+        for (name, expected) in cases {
+            assert_eq!(sanitize_filename(name), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn numbers_taken_names() {
+        let dir = std::env::temp_dir().join(format!(
+            "whatsapp-downloads-test-{}/unique",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "photo.jpg",
+            "photo (1).jpg",
+            "README",
+            ".bashrc",
+            "archive.tar.gz",
+        ] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let nothing_taken = |_: &Path| false;
+
+        let cases = [
+            ("new.jpg", "new.jpg"),
+            ("photo.jpg", "photo (2).jpg"),
+            ("README", "README (1)"),
+            (".bashrc", ".bashrc (1)"),
+            ("archive.tar.gz", "archive.tar (1).gz"),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(
+                unique_path(&dir, name, nothing_taken),
+                dir.join(expected),
+                "{}",
+                name
+            );
+        }
+
+        // Names reserved by downloads still running count as taken
+        let running = [dir.join("new.jpg"), dir.join("new (1).jpg")];
+        assert_eq!(
+            unique_path(&dir, "new.jpg", |path| running.iter().any(|r| r == path)),
+            dir.join("new (2).jpg")
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Dangerous-file guard
+//!
+//! Any contact can send a `.desktop` launcher, a shell script or an ELF
+//! binary, and handing those to xdg-open may run them. Files that look
+//! executable (denylisted extension, executable bit or executable magic) are
+//! never opened automatically; depending on [`DangerousFilePolicy`] they are
+//! kept after asking, kept silently, or opened like any other file.
+
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Bytes read to look for executable magic
+const HEADER_LEN: u64 = 512;
+
+/// What happens when a download that would be opened looks dangerous
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DangerousFilePolicy {
+    /// Keep the file and ask before opening it
+    #[default]
+    Ask,
+    /// Keep the file, never open it
+    Keep,
+    /// Open it like any other file
+    Allow,
+}
+
+/// Why a file is considered dangerous
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Danger {
+    /// Extension on the denylist
+    Extension(String),
+    /// The executable bit is set
+    ExecutableBit,
+    /// Contents start like a program, named by what was found
+    Magic(&'static str),
+}
+
+impl Danger {
+    /// Short explanation for the confirmation prompt
+    pub fn describe(&self) -> String {
+        match self {
+            Danger::Extension(ext) => format!("is a .{} file", ext),
+            Danger::ExecutableBit => "is marked as executable".to_string(),
+            Danger::Magic(what) => format!("is {}", what),
+        }
+    }
+}
+
+/// Linux launchers, executables and packages
+const LINUX_EXECUTABLES: &[&str] = &[
+    "desktop",
+    "appimage",
+    "run",
+    "bin",
+    "elf",
+    "so",
+    "deb",
+    "rpm",
+    "snap",
+    "flatpak",
+    "flatpakref",
+    "flatpakrepo",
+];
+
+/// Scripts an interpreter would run
+const SCRIPTS: &[&str] = &[
+    "sh", "bash", "zsh", "csh", "ksh", "fish", "py", "pl", "rb", "php", "js", "jar",
+];
+
+/// Windows executables, which Wine may run
+const WINDOWS_EXECUTABLES: &[&str] = &[
+    "exe", "msi", "com", "scr", "bat", "cmd", "ps1", "vbs", "wsf", "hta", "lnk",
+];
+
+/// The built-in extension denylist
+pub fn default_denylist() -> Vec<String> {
+    LINUX_EXECUTABLES
+        .iter()
+        .chain(SCRIPTS)
+        .chain(WINDOWS_EXECUTABLES)
+        .map(|e| e.to_string())
+        .collect()
+}
+
+/// Check a file against the denylist, its permissions and its contents
+pub fn check(path: &Path, denylist: &[String]) -> Option<Danger> {
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        if denylist
+            .iter()
+            .any(|d| d.trim_start_matches('.').eq_ignore_ascii_case(ext))
+        {
+            return Some(Danger::Extension(ext.to_lowercase()));
+        }
+    }
+
+    let mut header = Vec::new();
+    if let Ok(file) = File::open(path) {
+        let _ = file.take(HEADER_LEN).read_to_end(&mut header);
+    }
+    if let Some(what) = executable_magic(&header) {
+        return Some(Danger::Magic(what));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Ok(meta) = std::fs::metadata(path) {
+            if meta.is_file() && meta.permissions().mode() & 0o111 != 0 {
+                return Some(Danger::ExecutableBit);
+            }
+        }
+    }
+
+    None
+}
+
+/// Recognize programs by their first bytes
+pub fn executable_magic(header: &[u8]) -> Option<&'static str> {
+    if header.starts_with(b"\x7fELF") {
+        return Some("a Linux program (ELF)");
+    }
+    if header.starts_with(b"#!") {
+        return Some("a script");
+    }
+    if header.starts_with(b"MZ") {
+        return Some("a Windows program");
+    }
+
+    // Desktop entries may start with comments or blank lines
+    let text = String::from_utf8_lossy(header);
+    let first = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'));
+    if first == Some("[Desktop Entry]") {
+        return Some("a desktop launcher");
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A fresh directory for one test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("whatsapp-guard-test-{}", std::process::id()))
+            .join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn denylisted_extensions() {
+        let dir = temp_dir("extensions");
+        let denylist = default_denylist();
+        let cases = [
+            ("launcher.desktop", Some("desktop")),
+            ("SETUP.EXE", Some("exe")),
+            ("Invoice.pdf.exe", Some("exe")),
+            ("script.Sh", Some("sh")),
+            ("invoice.pdf", None),
+            ("exe", None),
+            ("photo.exe.jpg", None),
+        ];
+
+        for (name, expected) in cases {
+            let path = dir.join(name);
+            fs::write(&path, "harmless text").unwrap();
+            let expected = expected.map(|ext| Danger::Extension(ext.to_string()));
+            assert_eq!(check(&path, &denylist), expected, "{}", name);
+        }
+
+        // Entries may be written with a dot
+        let path = dir.join("archive.ZIP");
+        fs::write(&path, "harmless text").unwrap();
+        assert_eq!(
+            check(&path, &[".zip".to_string()]),
+            Some(Danger::Extension("zip".to_string()))
+        );
+        assert_eq!(check(&path, &[]), None);
+    }
+
+    #[test]
+    fn recognizes_programs() {
+        let cases: &[(&[u8], Option<&str>)] = &[
+            (b"\x7fELF\x02\x01\x01", Some("a Linux program (ELF)")),
+            (b"MZ\x90\x00", Some("a Windows program")),
+            (b"#!/bin/sh\nrm -rf ~\n", Some("a script")),
+            (b"[Desktop Entry]\nExec=sh\n", Some("a desktop launcher")),
+            (
+                b"# Created by hand\n\n  # another comment\n[Desktop Entry]\nType=Application\n",
+                Some("a desktop launcher"),
+            ),
+            (b"\xef\xbb\xbfhello", None),
+            (b"[Desktop Action new]\n[Desktop Entry]\n", None),
+            (b"# Notes\nsee [Desktop Entry]\n", None),
+            (b"%PDF-1.7", None),
+            (b"ELF", None),
+            (b"", None),
+        ];
+
+        for (header, expected) in cases {
+            assert_eq!(
+                executable_magic(header),
+                *expected,
+                "{}",
+                String::from_utf8_lossy(header)
+            );
+        }
+    }
+
+    #[test]
+    fn checks_contents_of_innocent_names() {
+        let dir = temp_dir("contents");
+        let path = dir.join("photo.jpg");
+        fs::write(&path, b"\x7fELF\x02\x01\x01\x00").unwrap();
+
+        assert_eq!(
+            check(&path, &default_denylist()),
+            Some(Danger::Magic("a Linux program (ELF)"))
+        );
+        assert_eq!(check(&dir.join("missing.jpg"), &default_denylist()), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn executable_bit() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("exec-bit");
+        let path = dir.join("notes.txt");
+        fs::write(&path, "just text").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(check(&path, &default_denylist()), None);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o744)).unwrap();
+        assert_eq!(
+            check(&path, &default_denylist()),
+            Some(Danger::ExecutableBit)
+        );
+
+        // Directories are searchable, not executable files
+        assert_eq!(check(&dir, &default_denylist()), None);
+    }
+}
@@ -0,0 +1,433 @@
+//! Persistent download history
+//!
+//! Every processed download is recorded in `history.json` in the data dir,
+//! newest last. The Ctrl+J overlay lists it and sends [`HistoryCommand`]s
+//! back over IPC to re-open, reveal or delete a file. The page can send those
+//! on its own, so deleting is confirmed in a native dialog first.
+
+use crate::desktop;
+use crate::dialogs::Chooser;
+use crate::postprocess::{AfterDownload, DownloadReport};
+use crate::rules::OpenAction;
+use crate::sniff::{self, Category};
+use crate::{handle_file_open, Config, UserEvent};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tao::event_loop::EventLoopProxy;
+
+/// Beyond this, the oldest entries of files that are gone are dropped
+const MAX_ENTRIES: usize = 5000;
+
+/// One finished download
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub url: String,
+    /// Final location after renaming and sorting
+    pub path: PathBuf,
+    /// Type detected from the contents, named by its usual extension
+    pub kind: Option<String>,
+    pub category: Category,
+    /// Size in bytes when the download finished
+    pub size: u64,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub after: AfterDownload,
+    /// Open rule that ran, if any
+    pub action: Option<OpenAction>,
+    /// Content hash, see [`crate::dedup::hash_file`]
+    pub hash: Option<String>,
+    /// Earlier entry with the same contents, if this download was deduplicated
+    pub duplicate_of: Option<u64>,
+}
+
+/// What the history overlay asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum HistoryCommand {
+    Open(u64),
+    Reveal(u64),
+    Delete(u64),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct History {
+    pub entries: Vec<HistoryEntry>,
+    next_id: u64,
+}
+
+impl History {
+    fn history_path() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("whatsapp-desktop")
+            .join("history.json")
+    }
+
+    pub fn load() -> Self {
+        let path = Self::history_path();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                eprintln!("[HISTORY] Can't read {:?}, starting empty: {}", path, e);
+                return Self::default();
+            }
+        };
+
+        match serde_json::from_str(&content) {
+            Ok(history) => history,
+            Err(e) => {
+                eprintln!(
+                    "[HISTORY] Can't parse {:?}, starting empty until it is fixed: {}",
+                    path, e
+                );
+                Self::default()
+            }
+        }
+    }
+
+    /// Write the history, unless the file on disk doesn't parse: that is left
+    /// for the user to fix or remove rather than overwritten
+    pub fn save(&self) {
+        let path = Self::history_path();
+        if let Ok(existing) = fs::read_to_string(&path) {
+            if let Err(e) = serde_json::from_str::<History>(&existing) {
+                eprintln!(
+                    "[HISTORY] Not saving over {:?}, it doesn't parse: {}",
+                    path, e
+                );
+                return;
+            }
+        }
+
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        match serde_json::to_string(self) {
+            Ok(content) => {
+                if let Err(e) = fs::write(&path, content) {
+                    eprintln!("[HISTORY] Failed to save {:?}: {}", path, e);
+                }
+            }
+            Err(e) => eprintln!("[HISTORY] Failed to serialize: {}", e),
+        }
+    }
+
+    /// Add a processed download and return its id
+    pub fn record(&mut self, report: &DownloadReport) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.entries.push(HistoryEntry {
+            id,
+            url: report.url.clone(),
+            path: report.path.clone(),
+            kind: sniff::sniff_file(&report.path).map(|k| k.extension().to_string()),
+            category: Category::of(&report.path),
+            size: fs::metadata(&report.path).map(|m| m.len()).unwrap_or(0),
+            timestamp: now(),
+            after: report.after,
+            action: report.action.clone(),
+            hash: report.hash.clone(),
+            duplicate_of: report.duplicate_of,
+        });
+
+        self.trim();
+        id
+    }
+
+    /// Forget the oldest entries whose files no longer exist. Entries of files
+    /// still on disk are kept whatever their number, since retention only
+    /// finds downloads through the history.
+    fn trim(&mut self) {
+        let mut excess = self.entries.len().saturating_sub(MAX_ENTRIES);
+        if excess == 0 {
+            return;
+        }
+        self.entries.retain(|entry| {
+            if excess > 0 && !entry.path.exists() {
+                excess -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    pub fn get(&self, id: u64) -> Option<&HistoryEntry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<HistoryEntry> {
+        let index = self.entries.iter().position(|e| e.id == id)?;
+        Some(self.entries.remove(index))
+    }
+
+    /// JSON for the overlay, newest first, with whether each file still exists
+    pub fn overlay_json(&self) -> String {
+        let items: Vec<_> = self
+            .entries
+            .iter()
+            .rev()
+            .map(|e| {
+                serde_json::json!({
+                    "id": e.id,
+                    "name": e.path.file_name().map(|n| n.to_string_lossy()),
+                    "path": e.path.to_string_lossy(),
+                    "url": e.url,
+                    "kind": e.kind,
+                    "category": e.category,
+                    "size": e.size,
+                    "timestamp": e.timestamp,
+                    "exists": e.path.exists(),
+                })
+            })
+            .collect();
+
+        serde_json::to_string(&items).unwrap_or_else(|_| "[]".to_string())
+    }
+}
+
+/// Carry out an overlay command. Opening and deleting may ask the user, so
+/// they run on a thread of their own; a deletion is followed by
+/// `UserEvent::HistoryChanged`.
+pub fn run_command(
+    command: HistoryCommand,
+    history: &Arc<Mutex<History>>,
+    config: &Arc<Mutex<Config>>,
+    chooser: &Chooser,
+    proxy: &EventLoopProxy<UserEvent>,
+) {
+    let store = Arc::clone(history);
+    let Ok(history) = history.lock() else {
+        return;
+    };
+
+    match command {
+        HistoryCommand::Open(id) => {
+            let Some(path) = history.get(id).map(|e| e.path.clone()) else {
+                return;
+            };
+            let config = config.clone();
+            let chooser = chooser.clone();
+            let spawned = thread::Builder::new()
+                .name("history-open".to_string())
+                .spawn(move || {
+                    handle_file_open(&path, &config, &chooser);
+                });
+            if let Err(e) = spawned {
+                eprintln!("[HISTORY] Failed to start open thread: {}", e);
+            }
+        }
+        HistoryCommand::Reveal(id) => {
+            let path = history.get(id).map(|e| e.path.clone());
+            // The D-Bus call may take a while; don't block workers recording
+            drop(history);
+            if let Some(path) = path {
+                desktop::reveal_in_folder(&path);
+            }
+        }
+        HistoryCommand::Delete(id) => {
+            let Some(path) = history.get(id).map(|e| e.path.clone()) else {
+                return;
+            };
+            drop(history);
+
+            // Only the entry is left; nothing on disk to ask about
+            if !path.exists() {
+                delete(id, &store);
+                let _ = proxy.send_event(UserEvent::HistoryChanged);
+                return;
+            }
+
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let chooser = chooser.clone();
+            let proxy = proxy.clone();
+            let spawned = thread::Builder::new()
+                .name("history-delete".to_string())
+                .spawn(move || {
+                    // Keeping it comes first, so it is what a careless Enter picks
+                    let text = format!("Delete \"{}\" from your disk?", name);
+                    let options = ["Keep it", "Delete"];
+                    if chooser.confirm("Delete download", &text, &options, "OK") != Some(1) {
+                        return;
+                    }
+                    delete(id, &store);
+                    let _ = proxy.send_event(UserEvent::HistoryChanged);
+                });
+            if let Err(e) = spawned {
+                eprintln!("[HISTORY] Failed to start delete thread: {}", e);
+            }
+        }
+    }
+}
+
+/// Delete a download's file and its entry
+fn delete(id: u64, history: &Mutex<History>) {
+    let Ok(mut history) = history.lock() else {
+        return;
+    };
+    let Some(path) = history.get(id).map(|e| e.path.clone()) else {
+        return;
+    };
+
+    // Deduplicated downloads share the file with other entries
+    let shared = history.entries.iter().any(|e| e.id != id && e.path == path);
+    if shared {
+        eprintln!("[HISTORY] Keeping {:?}, other downloads refer to it", path);
+        history.remove(id);
+        history.save();
+        return;
+    }
+
+    eprintln!("[HISTORY] Deleting {:?}", path);
+    match fs::remove_file(&path) {
+        Ok(()) => {}
+        // Already gone; just drop the entry
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            eprintln!("[HISTORY] Failed to delete {:?}: {}", path, e);
+            return;
+        }
+    }
+    history.remove(id);
+    history.save();
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Injected into every page: the Ctrl+J overlay. Rust fills it through
+/// `window.__downloadHistory.show(items)`; buttons answer over IPC.
+pub const HISTORY_OVERLAY_SCRIPT: &str = r#"
+(function() {
+    'use strict';
+
+    const send = (message) => window.ipc.postMessage(JSON.stringify(message));
+
+    function formatSize(bytes) {
+        if (bytes < 1024) return bytes + ' B';
+        if (bytes < 1024 * 1024) return Math.round(bytes / 1024) + ' KB';
+        return (bytes / (1024 * 1024)).toFixed(1) + ' MB';
+    }
+
+    function close() {
+        const overlay = document.getElementById('download-history');
+        if (overlay) overlay.remove();
+    }
+
+    function render(list, items, query) {
+        list.replaceChildren();
+        const needle = query.trim().toLowerCase();
+        const matching = items.filter((item) => !needle ||
+            [item.name, item.url, item.kind, item.category]
+                .some((field) => field && String(field).toLowerCase().includes(needle)));
+
+        if (matching.length === 0) {
+            const empty = document.createElement('div');
+            empty.style.cssText = 'padding:16px;color:#8696a0;text-align:center;';
+            empty.textContent = items.length ? 'No matching downloads' : 'No downloads yet';
+            list.appendChild(empty);
+            return;
+        }
+
+        for (const item of matching) {
+            const row = document.createElement('div');
+            row.style.cssText = 'display:flex;align-items:center;gap:8px;padding:8px 4px;border-bottom:1px solid #2a3942;';
+
+            const info = document.createElement('div');
+            info.style.cssText = 'flex:1;min-width:0;';
+            const name = document.createElement('div');
+            name.style.cssText = 'white-space:nowrap;overflow:hidden;text-overflow:ellipsis;' +
+                (item.exists ? '' : 'text-decoration:line-through;color:#8696a0;');
+            name.textContent = item.name || item.path;
+            name.title = item.path;
+            const details = document.createElement('div');
+            details.style.cssText = 'font-size:11px;color:#8696a0;';
+            details.textContent = [
+                new Date(item.timestamp * 1000).toLocaleString(),
+                item.kind ? item.kind.toUpperCase() : item.category,
+                formatSize(item.size),
+                item.exists ? '' : 'missing',
+            ].filter(Boolean).join(' · ');
+            info.append(name, details);
+            row.appendChild(info);
+
+            for (const [label, command] of [['Open', 'Open'], ['Show', 'Reveal'], ['Delete', 'Delete']]) {
+                const button = document.createElement('button');
+                button.textContent = label;
+                button.disabled = !item.exists && command !== 'Delete';
+                button.style.cssText = 'background:#2a3942;color:#e9edef;border:none;border-radius:6px;padding:4px 10px;cursor:pointer;font-size:12px;';
+                // Deleting is confirmed natively, outside the page
+                button.onclick = () => send({ History: { [command]: item.id } });
+                row.appendChild(button);
+            }
+
+            list.appendChild(row);
+        }
+    }
+
+    function show(items) {
+        const existing = document.getElementById('download-history');
+        const query = existing ? existing.querySelector('input').value : '';
+        close();
+
+        const overlay = document.createElement('div');
+        overlay.id = 'download-history';
+        overlay.style.cssText = 'position:fixed;inset:0;background:rgba(0,0,0,0.5);z-index:1000000;display:flex;align-items:center;justify-content:center;';
+        overlay.onclick = (e) => { if (e.target === overlay) close(); };
+
+        const panel = document.createElement('div');
+        panel.style.cssText = 'background:#111b21;color:#e9edef;width:min(720px,90vw);max-height:80vh;border-radius:12px;padding:16px;display:flex;flex-direction:column;gap:8px;font-size:14px;box-shadow:0 4px 20px rgba(0,0,0,0.4);';
+
+        const title = document.createElement('div');
+        title.style.cssText = 'font-size:16px;font-weight:bold;';
+        title.textContent = 'Downloads';
+
+        const search = document.createElement('input');
+        search.placeholder = 'Search downloads';
+        search.value = query;
+        search.style.cssText = 'background:#2a3942;color:#e9edef;border:none;border-radius:8px;padding:8px;outline:none;';
+
+        const list = document.createElement('div');
+        list.style.cssText = 'overflow-y:auto;';
+
+        search.oninput = () => render(list, items, search.value);
+        render(list, items, query);
+
+        panel.append(title, search, list);
+        overlay.appendChild(panel);
+        document.body.appendChild(overlay);
+        search.focus();
+    }
+
+    window.__downloadHistory = { show, close };
+
+    document.addEventListener('keydown', function(e) {
+        if (e.key === 'Escape' && document.getElementById('download-history')) {
+            e.preventDefault();
+            close();
+        } else if (e.ctrlKey && !e.shiftKey && (e.key === 'j' || e.key === 'J')) {
+            e.preventDefault();
+            e.stopPropagation();
+            if (document.getElementById('download-history')) {
+                close();
+            } else {
+                send('ShowHistory');
+            }
+        }
+    }, true);
+})();
+"#;
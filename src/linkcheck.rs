@@ -0,0 +1,400 @@
+//! Safety checks for external links
+//!
+//! Before a link is launched it is checked for common phishing tricks:
+//! internationalized (punycode) hosts that may imitate another domain, raw IP
+//! hosts, a user name in front of the host, schemes other than http(s), URL
+//! shorteners hiding the destination, and link text naming a different site
+//! than the link goes to. Flagged links get a native confirmation dialog that
+//! shows the decoded destination; the answer comes back as a
+//! [`LinkDecision`]. The page never sees the dialog, so it can't open a
+//! flagged link by answering for the user.
+
+use percent_encoding::percent_decode_str;
+
+/// What the user answered to a warning
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkDecision {
+    Open(u64),
+    Cancel(u64),
+}
+
+/// Something suspicious about a link
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkWarning {
+    /// The link couldn't be parsed at all
+    Malformed,
+    /// The host has punycode labels; `decoded` is how it would display
+    Idn { decoded: String },
+    /// The host is an IP address instead of a name
+    IpHost(String),
+    /// A user name before the host, as in `https://bank.com@evil.example`
+    UserInfo(String),
+    /// Neither http nor https
+    Scheme(String),
+    /// A known URL shortener; the destination is unknown
+    Shortener(String),
+    /// The link text names another host than the link goes to
+    MismatchedText { text: String, host: String },
+}
+
+impl LinkWarning {
+    pub fn describe(&self) -> String {
+        match self {
+            LinkWarning::Malformed => "The link is malformed".to_string(),
+            LinkWarning::Idn { decoded } => format!(
+                "The address uses international characters ({}) and may imitate another site",
+                decoded
+            ),
+            LinkWarning::IpHost(host) => {
+                format!("The link goes to a bare IP address ({})", host)
+            }
+            LinkWarning::UserInfo(user) => {
+                format!("The link hides its real destination behind \"{}@\"", user)
+            }
+            LinkWarning::Scheme(scheme) => {
+                format!("The link opens a \"{}:\" address, not a web page", scheme)
+            }
+            LinkWarning::Shortener(host) => {
+                format!(
+                    "{} is a link shortener; the real destination is hidden",
+                    host
+                )
+            }
+            LinkWarning::MismatchedText { text, host } => {
+                format!("The link text says \"{}\" but it goes to {}", text, host)
+            }
+        }
+    }
+}
+
+/// Common URL shorteners
+pub fn default_shorteners() -> Vec<String> {
+    [
+        "bit.ly",
+        "buff.ly",
+        "cutt.ly",
+        "goo.gl",
+        "is.gd",
+        "lnkd.in",
+        "ow.ly",
+        "rb.gy",
+        "rebrand.ly",
+        "s.id",
+        "shorturl.at",
+        "t.co",
+        "t.ly",
+        "tiny.cc",
+        "tinyurl.com",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
+/// Check a link, with the text it was shown as if known
+pub fn check(link: &str, text: Option<&str>, shorteners: &[String]) -> Vec<LinkWarning> {
+    let Ok(url) = url::Url::parse(link) else {
+        return vec![LinkWarning::Malformed];
+    };
+
+    let mut warnings = Vec::new();
+
+    if !matches!(url.scheme(), "http" | "https") {
+        warnings.push(LinkWarning::Scheme(url.scheme().to_string()));
+    }
+
+    if !url.username().is_empty() {
+        warnings.push(LinkWarning::UserInfo(
+            percent_decode_str(url.username())
+                .decode_utf8_lossy()
+                .into_owned(),
+        ));
+    }
+
+    match url.host() {
+        Some(url::Host::Ipv4(ip)) => warnings.push(LinkWarning::IpHost(ip.to_string())),
+        Some(url::Host::Ipv6(ip)) => warnings.push(LinkWarning::IpHost(ip.to_string())),
+        Some(url::Host::Domain(host)) => {
+            let host = host.to_lowercase();
+
+            if host.split('.').any(|label| label.starts_with("xn--")) {
+                warnings.push(LinkWarning::Idn {
+                    decoded: idna::domain_to_unicode(&host).0,
+                });
+            }
+
+            if shorteners.iter().any(|s| is_same_or_subdomain(&host, s)) {
+                warnings.push(LinkWarning::Shortener(host.clone()));
+            }
+
+            if let Some(text) = text.map(str::trim).filter(|t| !t.is_empty()) {
+                if let Some(shown) = host_in_text(text) {
+                    if !same_site(&shown, &host) {
+                        warnings.push(LinkWarning::MismatchedText {
+                            text: text.to_string(),
+                            host: display_host(&host),
+                        });
+                    }
+                }
+            }
+        }
+        None => {}
+    }
+
+    warnings
+}
+
+/// The link with its host in Unicode and everything else percent-decoded
+pub fn decode(link: &str) -> String {
+    let Ok(url) = url::Url::parse(link) else {
+        return percent_decode_str(link).decode_utf8_lossy().into_owned();
+    };
+
+    let Some(host) = url.host_str() else {
+        return percent_decode_str(link).decode_utf8_lossy().into_owned();
+    };
+
+    let mut decoded = format!("{}://", url.scheme());
+    if !url.username().is_empty() {
+        decoded.push_str(&percent_decode_str(url.username()).decode_utf8_lossy());
+        decoded.push('@');
+    }
+    decoded.push_str(&display_host(host));
+    if let Some(port) = url.port() {
+        decoded.push_str(&format!(":{}", port));
+    }
+
+    // Everything after the host
+    let rest = &url[url::Position::BeforePath..];
+    decoded.push_str(&percent_decode_str(rest).decode_utf8_lossy());
+    decoded
+}
+
+/// Unicode form of a host, with the punycode it really is in brackets
+fn display_host(host: &str) -> String {
+    let unicode = idna::domain_to_unicode(host).0;
+    if unicode == host {
+        host.to_string()
+    } else {
+        format!("{} [{}]", unicode, host)
+    }
+}
+
+/// Host of link text that looks like an address, e.g. `paypal.com/login`
+fn host_in_text(text: &str) -> Option<String> {
+    if text.contains(char::is_whitespace) || !text.contains('.') {
+        return None;
+    }
+
+    let parsed = url::Url::parse(text)
+        .ok()
+        .filter(|u| u.has_host())
+        .or_else(|| url::Url::parse(&format!("https://{}", text)).ok())?;
+
+    let host = parsed.host_str()?.to_lowercase();
+    // "e.g." or "v1.2" are not hosts
+    let tld = host.rsplit('.').next()?;
+    if tld.len() < 2 || !tld.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some(host)
+}
+
+fn is_same_or_subdomain(host: &str, domain: &str) -> bool {
+    let domain = domain.trim().trim_start_matches('.').to_lowercase();
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+/// The link goes to the host shown or one of its subdomains, ignoring a
+/// leading `www.`: text "google.com" may go to `accounts.google.com`
+fn same_site(shown: &str, host: &str) -> bool {
+    is_same_or_subdomain(
+        host.trim_start_matches("www."),
+        shown.trim_start_matches("www."),
+    )
+}
+
+/// Injected into every page: reports the text of clicked links, so it can be
+/// compared with where they go
+pub const LINK_WARNING_SCRIPT: &str = r#"
+(function() {
+    'use strict';
+
+    const send = (message) => window.ipc.postMessage(JSON.stringify(message));
+
+    // Reported before the link opens, so its text can be compared
+    function recordClick(e) {
+        const link = e.target && e.target.closest ? e.target.closest('a[href]') : null;
+        if (!link) return;
+        send({ LinkClicked: { url: link.href, text: (link.innerText || '').trim().slice(0, 500) } });
+    }
+    document.addEventListener('click', recordClick, true);
+    document.addEventListener('auxclick', recordClick, true);
+})();
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warnings(link: &str, text: Option<&str>) -> Vec<LinkWarning> {
+        check(link, text, &default_shorteners())
+    }
+
+    #[test]
+    fn check_flags_tricks() {
+        use LinkWarning::*;
+        let mismatched = |text: &str, host: &str| MismatchedText {
+            text: text.to_string(),
+            host: host.to_string(),
+        };
+
+        let cases: &[(&str, Option<&str>, Vec<LinkWarning>)] = &[
+            ("https://example.com/page", None, vec![]),
+            ("not a link", None, vec![Malformed]),
+            (
+                "https://xn--80ak6aa92e.com/",
+                None,
+                vec![Idn {
+                    decoded: "\u{430}\u{440}\u{440}\u{4cf}\u{435}.com".to_string(),
+                }],
+            ),
+            (
+                "https://192.168.1.1/login",
+                None,
+                vec![IpHost("192.168.1.1".to_string())],
+            ),
+            // Shorthand IPv4 forms are normalized by the parser
+            (
+                "http://0x7f.1/",
+                None,
+                vec![IpHost("127.0.0.1".to_string())],
+            ),
+            ("http://[::1]:8080/", None, vec![IpHost("::1".to_string())]),
+            (
+                "https://bank.com@evil.example/",
+                None,
+                vec![UserInfo("bank.com".to_string())],
+            ),
+            (
+                "javascript:alert(1)",
+                None,
+                vec![Scheme("javascript".to_string())],
+            ),
+            ("file:///etc/passwd", None, vec![Scheme("file".to_string())]),
+            (
+                "https://bit.ly/abc",
+                None,
+                vec![Shortener("bit.ly".to_string())],
+            ),
+            (
+                "https://go.bit.ly/abc",
+                None,
+                vec![Shortener("go.bit.ly".to_string())],
+            ),
+            // Only the shortener's own subdomains, not look-alikes
+            ("https://habit.ly/abc", None, vec![]),
+            (
+                "https://evil.example/login",
+                Some("paypal.com"),
+                vec![mismatched("paypal.com", "evil.example")],
+            ),
+            (
+                "https://evil.example/",
+                Some("https://www.paypal.com/signin"),
+                vec![mismatched("https://www.paypal.com/signin", "evil.example")],
+            ),
+            // A look-alike shown host, even if the real one is its suffix
+            (
+                "https://example.com/",
+                Some("paypal.com.example.com"),
+                vec![mismatched("paypal.com.example.com", "example.com")],
+            ),
+            (
+                "https://example.com/",
+                Some("example.com.evil.example"),
+                vec![mismatched("example.com.evil.example", "example.com")],
+            ),
+            ("https://www.paypal.com/", Some("paypal.com"), vec![]),
+            ("https://paypal.com/", Some("www.PayPal.com"), vec![]),
+            ("https://accounts.google.com/", Some("google.com"), vec![]),
+            (
+                "https://google.com/",
+                Some("accounts.google.com"),
+                vec![mismatched("accounts.google.com", "google.com")],
+            ),
+            // Text that isn't an address
+            ("https://example.com/", Some("click here"), vec![]),
+            ("https://example.com/", Some("e.g."), vec![]),
+            ("https://example.com/", Some("v1.2"), vec![]),
+            ("https://example.com/", Some("see the docs."), vec![]),
+            ("https://example.com/", Some("   "), vec![]),
+        ];
+
+        for (link, text, expected) in cases {
+            assert_eq!(&warnings(link, *text), expected, "{} {:?}", link, text);
+        }
+    }
+
+    #[test]
+    fn host_in_text_finds_addresses() {
+        let cases = [
+            ("paypal.com", Some("paypal.com")),
+            ("PayPal.com/login", Some("paypal.com")),
+            ("https://example.org/a?b=c", Some("example.org")),
+            ("example.com:8080", Some("example.com")),
+            ("e.g.", None),
+            ("v1.2", None),
+            ("3.14", None),
+            ("a.b", None),
+            ("no dots", None),
+            ("two words.com", None),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(host_in_text(text).as_deref(), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn same_site_allows_subdomains_of_the_shown_host() {
+        let cases = [
+            ("google.com", "google.com", true),
+            ("google.com", "www.google.com", true),
+            ("www.google.com", "google.com", true),
+            ("google.com", "accounts.google.com", true),
+            ("www.google.com", "accounts.google.com", true),
+            ("accounts.google.com", "google.com", false),
+            ("google.com", "notgoogle.com", false),
+            ("google.com", "google.com.evil.example", false),
+        ];
+
+        for (shown, host, expected) in cases {
+            assert_eq!(same_site(shown, host), expected, "{} {}", shown, host);
+        }
+    }
+
+    #[test]
+    fn decode_shows_the_real_destination() {
+        let cases = [
+            (
+                "https://example.com/a%20b?q=%C3%A9",
+                "https://example.com/a b?q=\u{e9}",
+            ),
+            (
+                "https://xn--80ak6aa92e.com/login",
+                "https://\u{430}\u{440}\u{440}\u{4cf}\u{435}.com [xn--80ak6aa92e.com]/login",
+            ),
+            (
+                "https://bank.com%2Flogin@evil.example:8443/",
+                "https://bank.com/login@evil.example:8443/",
+            ),
+            ("mailto:a%40b", "mailto:a@b"),
+            ("not%20a link", "not a link"),
+        ];
+
+        for (link, expected) in cases {
+            assert_eq!(decode(link), expected, "{}", link);
+        }
+    }
+}
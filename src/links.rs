@@ -0,0 +1,365 @@
+//! Routing of external links
+//!
+//! Links WhatsApp opens in a new window are matched against
+//! `Config::link_rules`, an ordered list of domain globs; the first rule
+//! matching the link's host decides where it goes. Links no rule matches are
+//! handed to the system, as before.
+//!
+//! The main webview itself may only navigate to origins on
+//! `Config::navigation_allowlist`; anything else is treated as an external
+//! link or blocked, see [`BlockedNavigation`].
+
+use crate::Browser;
+use serde::{Deserialize, Serialize};
+
+/// Where an external link goes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LinkTarget {
+    /// The system default handler (xdg-open)
+    System,
+    /// A specific browser, including custom ones and their profiles
+    Browser(Browser),
+    /// A secondary window of this app
+    Window,
+    /// Copy the link to the clipboard instead of opening it
+    Clipboard,
+    /// Don't open the link at all
+    Block,
+}
+
+/// Domain globs -> target
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkRule {
+    /// Host globs such as `zoom.us` or `*.atlassian.net`; `*` matches any
+    /// run of characters, `?` a single one
+    pub domains: Vec<String>,
+    pub target: LinkTarget,
+}
+
+impl LinkRule {
+    pub fn matches(&self, host: &str) -> bool {
+        self.domains
+            .iter()
+            .any(|glob| glob_matches(glob.trim(), host))
+    }
+}
+
+/// Lowercase host of a link; empty for links without one, like `mailto:`
+pub fn host_of(link: &str) -> String {
+    url::Url::parse(link)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_lowercase))
+        .unwrap_or_default()
+}
+
+/// Case-insensitive glob match of a whole host
+pub fn glob_matches(glob: &str, host: &str) -> bool {
+    let glob: Vec<char> = glob.to_lowercase().chars().collect();
+    let host: Vec<char> = host.to_lowercase().chars().collect();
+
+    let (mut g, mut h) = (0, 0);
+    // Position after the last `*` and the host position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while h < host.len() {
+        match glob.get(g) {
+            Some('*') => {
+                backtrack = Some((g + 1, h));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == host[h] => {
+                g += 1;
+                h += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character
+                Some((after_star, tried)) => {
+                    g = after_star;
+                    h = tried + 1;
+                    backtrack = Some((after_star, tried + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// Target of the first rule matching the link, or the system handler
+pub fn find_target(rules: &[LinkRule], link: &str) -> LinkTarget {
+    let host = host_of(link);
+    rules
+        .iter()
+        .find(|rule| rule.matches(&host))
+        .map(|rule| rule.target.clone())
+        .unwrap_or(LinkTarget::System)
+}
+
+/// What happens when the main webview tries to leave the allowlist
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BlockedNavigation {
+    /// Cancel it and open the page like an external link
+    #[default]
+    OpenExternally,
+    /// Cancel it
+    Block,
+}
+
+/// Origins WhatsApp Web needs
+pub fn default_navigation_allowlist() -> Vec<String> {
+    [
+        "https://web.whatsapp.com",
+        "https://mmg.whatsapp.net",
+        "https://static.whatsapp.net",
+        "https://pps.whatsapp.net",
+        "blob:",
+        "about:blank",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
+/// Whether the main webview may navigate to `link`. Entries ending in `:`
+/// allow a whole scheme; entries with `://` are origin globs such as
+/// `https://*.whatsapp.net`, which `blob:` URLs match by their creator's
+/// origin; anything else, like `about:blank`, is one exact URL.
+/// `data:` isn't allowed by default, since it would let any page be shown.
+pub fn navigation_allowed(allowlist: &[String], link: &str) -> bool {
+    let Ok(url) = url::Url::parse(link) else {
+        return false;
+    };
+    let scheme = format!("{}:", url.scheme());
+    let origin = url.origin().ascii_serialization();
+
+    allowlist.iter().map(|entry| entry.trim()).any(|entry| {
+        if entry.ends_with(':') {
+            entry.eq_ignore_ascii_case(&scheme)
+        } else if entry.contains("://") {
+            glob_matches(entry.trim_end_matches('/'), &origin)
+        } else {
+            entry.eq_ignore_ascii_case(&url[..url::Position::AfterQuery])
+        }
+    })
+}
+
+/// What the main webview does with a navigation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NavigationVerdict {
+    Allow,
+    Block,
+    /// Cancel it and hand the link to the external link path
+    OpenExternally,
+}
+
+/// Decide a navigation once it is known whether it replaces the page. The
+/// allowlist only applies to the page itself; embedded frames (the YouTube
+/// or Instagram players in chats) load freely. Only a link the user clicked
+/// may be sent to the browser, so a page can't open sites on its own.
+pub fn decide_navigation(
+    allowlist: &[String],
+    blocked: BlockedNavigation,
+    link: &str,
+    main_frame: bool,
+    clicked: bool,
+) -> NavigationVerdict {
+    if !main_frame || navigation_allowed(allowlist, link) {
+        return NavigationVerdict::Allow;
+    }
+    match blocked {
+        BlockedNavigation::OpenExternally if clicked => NavigationVerdict::OpenExternally,
+        _ => NavigationVerdict::Block,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracking::LinkCleaning;
+
+    fn rule(domains: &[&str], target: LinkTarget) -> LinkRule {
+        LinkRule {
+            domains: domains.iter().map(|d| d.to_string()).collect(),
+            target,
+        }
+    }
+
+    fn work_profile() -> Browser {
+        Browser::Custom {
+            name: "Work".to_string(),
+            private: false,
+            profile: true,
+        }
+    }
+
+    fn rules() -> Vec<LinkRule> {
+        vec![
+            rule(
+                &["zoom.us", "*.zoom.us"],
+                LinkTarget::Browser(Browser::Chrome),
+            ),
+            rule(&["*.atlassian.net"], LinkTarget::Browser(work_profile())),
+            rule(&["docs.example.com"], LinkTarget::Window),
+            rule(&["*.example.com"], LinkTarget::Clipboard),
+            rule(&["tracker-??.net"], LinkTarget::Block),
+        ]
+    }
+
+    #[test]
+    fn picks_the_first_matching_rule() {
+        let rules = rules();
+        let cases = [
+            (
+                "https://zoom.us/j/123",
+                LinkTarget::Browser(Browser::Chrome),
+            ),
+            (
+                "https://us02web.zoom.us/j/123?pwd=x",
+                LinkTarget::Browser(Browser::Chrome),
+            ),
+            (
+                "https://acme.atlassian.net/browse/OPS-1",
+                LinkTarget::Browser(work_profile()),
+            ),
+            // Listed before the wildcard, so it wins
+            ("https://docs.example.com/page", LinkTarget::Window),
+            ("https://wiki.example.com/page", LinkTarget::Clipboard),
+            ("https://tracker-01.net/pixel", LinkTarget::Block),
+            // Nothing matches: the system handler
+            ("https://tracker-001.net/pixel", LinkTarget::System),
+            ("https://example.com/", LinkTarget::System),
+            ("https://notzoom.us/", LinkTarget::System),
+            ("mailto:someone@example.com", LinkTarget::System),
+            ("not a link", LinkTarget::System),
+        ];
+
+        for (link, target) in cases {
+            assert_eq!(find_target(&rules, link), target, "{}", link);
+        }
+    }
+
+    #[test]
+    fn without_rules_everything_goes_to_the_system() {
+        assert_eq!(find_target(&[], "https://zoom.us/j/1"), LinkTarget::System);
+    }
+
+    #[test]
+    fn matches_hosts_case_insensitively() {
+        assert_eq!(
+            find_target(&rules(), "https://ACME.Atlassian.NET/"),
+            LinkTarget::Browser(work_profile())
+        );
+        assert!(rule(&[" Zoom.US "], LinkTarget::Window).matches("zoom.us"));
+    }
+
+    #[test]
+    fn globs() {
+        let cases = [
+            ("zoom.us", "zoom.us", true),
+            ("zoom.us", "us02web.zoom.us", false),
+            ("*.zoom.us", "us02web.zoom.us", true),
+            ("*.zoom.us", "zoom.us", false),
+            ("*", "anything.example", true),
+            ("*", "", true),
+            ("a*b*c", "axxbyyc", true),
+            ("a*b*c", "axxbyy", false),
+            ("?oom.us", "zoom.us", true),
+            ("?oom.us", "oom.us", false),
+            ("*.example.*", "docs.example.org", true),
+        ];
+
+        for (glob, host, expected) in cases {
+            assert_eq!(glob_matches(glob, host), expected, "{} vs {}", glob, host);
+        }
+    }
+
+    #[test]
+    fn cleaned_links_are_routed_by_their_real_host() {
+        let cleaning = LinkCleaning::default();
+        let link =
+            "https://l.facebook.com/l.php?u=https%3A%2F%2Fzoom.us%2Fj%2F42%3Futm_source%3Dfb&h=AT0";
+        let cleaned = cleaning.clean(link);
+
+        assert_eq!(cleaned, "https://zoom.us/j/42");
+        assert_eq!(
+            find_target(&rules(), &cleaned),
+            LinkTarget::Browser(Browser::Chrome)
+        );
+        // The redirector itself matches no rule
+        assert_eq!(find_target(&rules(), link), LinkTarget::System);
+    }
+
+    #[test]
+    fn navigation_allowlist() {
+        let allowlist = default_navigation_allowlist();
+        let cases = [
+            ("https://web.whatsapp.com/", true),
+            ("https://web.whatsapp.com/some/path?x=1", true),
+            ("https://mmg.whatsapp.net/v/t62/abc.enc", true),
+            ("blob:https://web.whatsapp.com/1234-5678", true),
+            ("about:blank", true),
+            ("about:blank#top", true),
+            ("data:text/plain,hello", false),
+            ("data:text/html,<h1>login</h1>", false),
+            ("about:srcdoc", false),
+            ("about:config", false),
+            ("http://web.whatsapp.com/", false),
+            ("https://web.whatsapp.com.evil.example/", false),
+            ("https://evil.example/?https://web.whatsapp.com", false),
+            ("https://www.whatsapp.com/", false),
+            ("javascript:alert(1)", false),
+            ("not a url", false),
+        ];
+
+        for (link, allowed) in cases {
+            assert_eq!(navigation_allowed(&allowlist, link), allowed, "{}", link);
+        }
+
+        let globbed = vec!["https://*.whatsapp.net".to_string()];
+        assert!(navigation_allowed(&globbed, "https://pps.whatsapp.net/x"));
+        assert!(!navigation_allowed(&globbed, "https://web.whatsapp.com/"));
+    }
+
+    #[test]
+    fn only_the_main_frame_is_restricted() {
+        use BlockedNavigation::*;
+        use NavigationVerdict as V;
+        let allowlist = default_navigation_allowlist();
+        let whatsapp = "https://web.whatsapp.com/";
+        let embed = "https://www.youtube.com/embed/abc";
+
+        // Link, setting, main frame, clicked by the user, verdict
+        let cases = [
+            (whatsapp, OpenExternally, true, false, V::Allow),
+            (whatsapp, Block, true, true, V::Allow),
+            // Embedded players load, clicked or not
+            (embed, OpenExternally, false, false, V::Allow),
+            (embed, Block, false, true, V::Allow),
+            // The page itself only leaves the allowlist for the browser,
+            // and only when the user clicked
+            (embed, OpenExternally, true, true, V::OpenExternally),
+            (embed, OpenExternally, true, false, V::Block),
+            (embed, Block, true, true, V::Block),
+            (
+                "data:text/html,<h1>login</h1>",
+                OpenExternally,
+                true,
+                false,
+                V::Block,
+            ),
+        ];
+
+        for (link, blocked, main_frame, clicked, verdict) in cases {
+            assert_eq!(
+                decide_navigation(&allowlist, blocked, link, main_frame, clicked),
+                verdict,
+                "{} ({:?}, main frame: {}, clicked: {})",
+                link,
+                blocked,
+                main_frame,
+                clicked
+            );
+        }
+    }
+}
@@ -3,16 +3,56 @@
 //! A minimal wry application that loads WhatsApp Web with a spoofed User-Agent.
 //! Supports voice message recording, file viewing, downloads, and notifications.
 
+mod browsers;
+mod capture;
+mod dedup;
+mod desktop;
+mod dialogs;
+mod downloads;
+mod guard;
+mod history;
+mod linkcheck;
+mod links;
+mod permissions;
+mod policy;
+mod postprocess;
+mod profile;
+mod progress;
+mod retention;
+mod rules;
+mod scan;
+mod sniff;
+mod tracking;
+
+use browsers::CustomBrowser;
+use capture::CaptureState;
+use dedup::DedupMode;
+use dialogs::{Chooser, ChooserRequest, DialogBackend};
+use guard::DangerousFilePolicy;
+use history::{History, HistoryCommand};
+use linkcheck::{LinkDecision, LinkWarning};
+use links::{BlockedNavigation, LinkRule, LinkTarget};
+use permissions::{PermissionAnswer, PermissionPolicy};
+use policy::Policy;
+use postprocess::{AfterDownload, DownloadJob, DownloadReport, PostProcessor};
+use profile::{Profile, ProfileEncryption};
+use progress::DownloadCommand;
+use retention::{RetentionPolicy, RetentionReport};
+use rules::{FileFacts, OpenAction, OpenRule};
+use scan::{ScanVerdict, Scanner};
 use serde::{Deserialize, Serialize};
-use std::fs::{self, File};
-use std::io::Read;
-use std::path::PathBuf;
+use sniff::Category;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::{Arc, Mutex};
 use tao::{
-    event::{Event, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::{Icon, WindowBuilder},
+    event::{Event, StartCause, WindowEvent},
+    event_loop::{ControlFlow, EventLoopBuilder, EventLoopWindowTarget},
+    window::{Icon, Window, WindowBuilder, WindowId},
 };
+use tracking::LinkCleaning;
 use wry::{WebContext, WebViewBuilder};
 
 /// WhatsApp Desktop macOS User-Agent - mimics official Electron app
@@ -235,36 +275,117 @@ const NOTIFICATION_MUTE_SCRIPT: &str = r#"
 })();
 "#;
 
+/// Events sent to the main event loop from other threads
+#[derive(Debug)]
+pub enum UserEvent {
+    /// A finished download went through post-processing
+    DownloadProcessed(DownloadReport),
+    /// A worker thread needs the user to pick an option
+    ShowChooser(ChooserRequest),
+    /// An injected script posted a message
+    Ipc(IpcMessage),
+    /// The retention policy ran
+    RetentionDone(RetentionReport),
+    /// A download was refused by the size or disk-space checks
+    DownloadRejected(String),
+    /// WhatsApp asked to open a link in a new window, or the main window
+    /// tried to navigate off the allowlist
+    OpenLink(String),
+    /// A permission chooser was answered
+    PermissionAnswered(PermissionAnswer),
+    /// A link warning was answered
+    LinkDecided(LinkDecision),
+    /// A download was deleted from the history
+    HistoryChanged,
+    /// The page started or stopped using the microphone, camera or screen
+    CaptureChanged(CaptureState),
+}
+
+/// Messages the injected scripts send through `window.ipc.postMessage`
+#[derive(Debug, Clone, Deserialize)]
+pub enum IpcMessage {
+    /// Ctrl+J was pressed
+    ShowHistory,
+    /// A button in the download history overlay was clicked
+    History(HistoryCommand),
+    /// A button in the download progress panel was clicked
+    Download(DownloadCommand),
+    /// A link was clicked; sent before it opens
+    LinkClicked { url: String, text: String },
+    /// The capture badge was clicked
+    StopCapture,
+}
+
 /// Supported browsers for opening PDFs
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Browser {
     Brave,
     Firefox,
     Chrome,
     Chromium,
     System, // Use xdg-open
+    /// Any browser found by its desktop file ID, e.g. `org.mozilla.firefox.desktop`
+    Desktop(String),
+    /// An entry of `custom_browsers`, optionally in a private window and/or
+    /// its configured profile
+    Custom {
+        name: String,
+        #[serde(default)]
+        private: bool,
+        #[serde(default)]
+        profile: bool,
+    },
 }
 
 impl Browser {
-    fn display_name(&self) -> &'static str {
+    fn display_name(&self) -> String {
         match self {
-            Browser::Brave => "Brave",
-            Browser::Firefox => "Firefox",
-            Browser::Chrome => "Google Chrome",
-            Browser::Chromium => "Chromium",
-            Browser::System => "System Default",
+            Browser::Brave => "Brave".to_string(),
+            Browser::Firefox => "Firefox".to_string(),
+            Browser::Chrome => "Google Chrome".to_string(),
+            Browser::Chromium => "Chromium".to_string(),
+            Browser::System => "System Default".to_string(),
+            Browser::Desktop(id) => browsers::find(id)
+                .map(|entry| entry.name)
+                .unwrap_or_else(|| id.clone()),
+            Browser::Custom {
+                name,
+                private,
+                profile,
+            } => match (private, profile) {
+                (false, false) => name.clone(),
+                (true, false) => format!("{} (private window)", name),
+                (false, true) => format!("{} (profile)", name),
+                (true, true) => format!("{} (profile, private window)", name),
+            },
         }
     }
 
+    /// Program and arguments that open `target`, a file or a link. `None` if
+    /// the desktop entry or custom browser is gone.
     #[cfg(target_os = "linux")]
-    fn command(&self) -> &'static str {
-        match self {
+    fn command_line(&self, target: &str, custom: &[CustomBrowser]) -> Option<Vec<String>> {
+        let command = match self {
             Browser::Brave => "brave-browser",
             Browser::Firefox => "firefox",
             Browser::Chrome => "google-chrome",
             Browser::Chromium => "chromium",
             Browser::System => "xdg-open",
-        }
+            Browser::Desktop(id) => {
+                return browsers::find(id).map(|entry| entry.command_line(target));
+            }
+            Browser::Custom {
+                name,
+                private,
+                profile,
+            } => {
+                return custom
+                    .iter()
+                    .find(|b| &b.name == name)?
+                    .command_line(target, *private, *profile);
+            }
+        };
+        Some(vec![command.to_string(), target.to_string()])
     }
 }
 
@@ -286,16 +407,93 @@ impl DocHandler {
 
 /// User preferences configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub pdf_browser: Option<Browser>,
+    /// User-defined browsers, offered alongside the detected ones
+    pub custom_browsers: Vec<CustomBrowser>,
+    /// Ordered external link rules, first match wins
+    pub link_rules: Vec<LinkRule>,
+    /// Ask before opening links that look like phishing
+    pub link_warnings: bool,
+    /// Hosts treated as URL shorteners by the link warnings
+    pub link_shorteners: Vec<String>,
+    /// Tracking parameter removal and redirector unwrapping for links
+    pub link_cleaning: LinkCleaning,
+    /// Origins the main window may navigate to, see `links::navigation_allowed`
+    pub navigation_allowlist: Vec<String>,
+    /// What happens to navigations outside the allowlist
+    pub blocked_navigation: BlockedNavigation,
+    /// Allow/deny/ask per permission type, and remembered answers per origin
+    pub permissions: PermissionPolicy,
+    /// Web inspector and developer extras; `--devtools` turns them on for one
+    /// run. The system policy can force them off.
+    pub devtools: bool,
+    /// Keep the WebKit profile and cookies in an encrypted file set
+    pub profile_encryption: ProfileEncryption,
     pub doc_handler: Option<DocHandler>,
+    /// Ordered "open with" rules, first match wins
+    pub open_rules: Vec<OpenRule>,
+    /// Native GTK choosers, or the zenity/kdialog fallback
+    pub dialog_backend: DialogBackend,
+    /// What happens once a download finished
+    pub after_download: AfterDownload,
+    /// Per-type overrides of `after_download`
+    pub after_download_by_type: BTreeMap<Category, AfterDownload>,
+    /// Download root; `None` follows `XDG_DOWNLOAD_DIR`
+    pub download_dir: Option<String>,
+    /// Subfolder template for finished downloads, e.g. `{type}/{yyyy-mm}`
+    pub download_subfolder: Option<String>,
+    /// Largest download accepted, in megabytes
+    pub max_download_mb: Option<u64>,
+    /// Megabytes that must stay free on the downloads filesystem
+    pub min_free_space_mb: u64,
+    /// What happens when a file about to be opened looks executable
+    pub dangerous_file_policy: DangerousFilePolicy,
+    /// Extensions that are never opened without asking
+    pub dangerous_extensions: Vec<String>,
+    /// Malware scanner run on every download, e.g. `clamdscan --fdpass {path}`
+    pub scan_command: Option<String>,
+    /// Seconds before a running scan is given up
+    pub scan_timeout_secs: u64,
+    /// Where flagged files are moved; defaults to the data dir
+    pub quarantine_dir: Option<String>,
+    /// What happens to downloads whose contents were downloaded before
+    pub dedup: DedupMode,
+    /// Automatic cleanup of old downloads
+    pub retention: RetentionPolicy,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             pdf_browser: None,
+            custom_browsers: Vec::new(),
+            link_rules: Vec::new(),
+            link_warnings: true,
+            link_shorteners: linkcheck::default_shorteners(),
+            link_cleaning: LinkCleaning::default(),
+            navigation_allowlist: links::default_navigation_allowlist(),
+            blocked_navigation: BlockedNavigation::OpenExternally,
+            permissions: PermissionPolicy::default(),
+            devtools: false,
+            profile_encryption: ProfileEncryption::Off,
             doc_handler: None,
+            open_rules: rules::default_rules(),
+            dialog_backend: DialogBackend::Native,
+            after_download: AfterDownload::Open,
+            after_download_by_type: BTreeMap::new(),
+            download_dir: None,
+            download_subfolder: None,
+            max_download_mb: None,
+            min_free_space_mb: 200,
+            dangerous_file_policy: DangerousFilePolicy::Ask,
+            dangerous_extensions: guard::default_denylist(),
+            scan_command: None,
+            scan_timeout_secs: 60,
+            quarantine_dir: None,
+            dedup: DedupMode::Reference,
+            retention: RetentionPolicy::default(),
         }
     }
 }
@@ -310,30 +508,112 @@ impl Config {
 
     pub fn load() -> Self {
         let path = Self::config_path();
-        if path.exists() {
-            if let Ok(content) = fs::read_to_string(&path) {
-                if let Ok(config) = serde_json::from_str(&content) {
-                    return config;
-                }
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                eprintln!("[CONFIG] Can't read {:?}, using defaults: {}", path, e);
+                return Self::default();
+            }
+        };
+
+        match serde_json::from_str(&content) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!(
+                    "[CONFIG] Can't parse {:?}, using defaults until it is fixed: {}",
+                    path, e
+                );
+                Self::default()
             }
         }
-        Self::default()
     }
 
+    /// The post-download action for a file category
+    pub fn after_download_for(&self, category: Category) -> AfterDownload {
+        self.after_download_by_type
+            .get(&category)
+            .copied()
+            .unwrap_or(self.after_download)
+    }
+
+    /// The configured malware scanner, if any
+    pub fn scanner(&self) -> Option<Scanner> {
+        let command = self.scan_command.as_deref().map(str::trim)?;
+        if command.is_empty() {
+            return None;
+        }
+
+        let quarantine_dir = match (self.quarantine_dir.as_deref(), dirs::home_dir()) {
+            (Some(dir), Some(home)) => downloads::expand_home(dir, &home),
+            (Some(dir), None) => PathBuf::from(dir),
+            (None, _) => scan::default_quarantine_dir(),
+        };
+
+        Some(Scanner {
+            command: command.to_string(),
+            timeout: std::time::Duration::from_secs(self.scan_timeout_secs.max(1)),
+            quarantine_dir,
+        })
+    }
+
+    /// Forget remembered "open with" choices so the next download prompts again
+    pub fn reset_open_preferences(&mut self) {
+        self.pdf_browser = None;
+        self.doc_handler = None;
+    }
+
+    /// Write the settings that differ from the built-in defaults, so the
+    /// others keep following the defaults. A config file that doesn't parse
+    /// is left for the user to fix rather than overwritten.
     pub fn save(&self) {
         let path = Self::config_path();
+        if let Ok(existing) = fs::read_to_string(&path) {
+            if let Err(e) = serde_json::from_str::<Config>(&existing) {
+                eprintln!(
+                    "[CONFIG] Not saving over {:?}, it doesn't parse: {}",
+                    path, e
+                );
+                return;
+            }
+        }
+
         if let Some(parent) = path.parent() {
             let _ = fs::create_dir_all(parent);
         }
-        if let Ok(content) = serde_json::to_string_pretty(self) {
+        if let Some(content) = self
+            .overrides()
+            .and_then(|overrides| serde_json::to_string_pretty(&overrides).ok())
+        {
             let _ = fs::write(&path, content);
         }
     }
+
+    /// Top-level settings that differ from the defaults. Values are kept
+    /// whole: a partial map would replace the whole default map when loaded.
+    fn overrides(&self) -> Option<serde_json::Value> {
+        let serde_json::Value::Object(mut settings) = serde_json::to_value(self).ok()? else {
+            return None;
+        };
+        let defaults = serde_json::to_value(Self::default()).ok()?;
+        settings.retain(|key, value| defaults.get(key) != Some(value));
+        Some(serde_json::Value::Object(settings))
+    }
 }
 
-/// Detect which browsers are installed on the system
+/// Detect which browsers are installed on the system, with their display
+/// names. Desktop entries are preferred; without any, fall back to looking
+/// for the well-known commands on `PATH`.
 #[cfg(target_os = "linux")]
-fn detect_installed_browsers() -> Vec<Browser> {
+fn detect_installed_browsers() -> Vec<(Browser, String)> {
+    let from_desktop: Vec<_> = browsers::installed_browsers()
+        .into_iter()
+        .map(|entry| (Browser::Desktop(entry.id), entry.name))
+        .collect();
+    if !from_desktop.is_empty() {
+        return from_desktop;
+    }
+
     let mut browsers = Vec::new();
 
     let browser_commands = [
@@ -350,71 +630,64 @@ fn detect_installed_browsers() -> Vec<Browser> {
             .map(|o| o.status.success())
             .unwrap_or(false)
         {
-            browsers.push(browser);
+            let name = browser.display_name();
+            browsers.push((browser, name));
         }
     }
 
     browsers
 }
 
-/// Show a dialog to let user pick from options using zenity
+/// Browsers to offer in the chooser: the detected ones, then each custom
+/// browser and its private window and profile variants
 #[cfg(target_os = "linux")]
-fn show_selection_dialog(title: &str, text: &str, options: &[&str]) -> Option<usize> {
-    // Try zenity first
-    let result = Command::new("zenity")
-        .args([
-            "--list",
-            "--radiolist",
-            "--title",
-            title,
-            "--text",
-            text,
-            "--column",
-            "Select",
-            "--column",
-            "Option",
-        ])
-        .args(
-            options
-                .iter()
-                .enumerate()
-                .flat_map(|(i, opt)| {
-                    if i == 0 {
-                        vec!["TRUE", *opt]
-                    } else {
-                        vec!["FALSE", *opt]
-                    }
-                })
-                .collect::<Vec<_>>(),
-        )
-        .output();
-
-    if let Ok(output) = result {
-        if output.status.success() {
-            let selected = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            return options.iter().position(|&o| o == selected);
+fn browser_choices(config: &Mutex<Config>) -> Vec<(Browser, String)> {
+    let mut choices = detect_installed_browsers();
+
+    let custom = config
+        .lock()
+        .map(|cfg| cfg.custom_browsers.clone())
+        .unwrap_or_default();
+    for entry in custom {
+        let variants = [
+            (false, false, true),
+            (true, false, entry.private_args.is_some()),
+            (false, true, entry.profile_args.is_some()),
+        ];
+        for (private, profile, available) in variants {
+            if available {
+                let browser = Browser::Custom {
+                    name: entry.name.clone(),
+                    private,
+                    profile,
+                };
+                let name = browser.display_name();
+                choices.push((browser, name));
+            }
         }
     }
 
-    // Fallback to kdialog
-    let result = Command::new("kdialog")
-        .args(["--menu", text])
-        .args(
-            options
-                .iter()
-                .flat_map(|opt| [opt, opt])
-                .collect::<Vec<_>>(),
-        )
-        .output();
-
-    if let Ok(output) = result {
-        if output.status.success() {
-            let selected = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            return options.iter().position(|&o| o == selected);
-        }
+    choices
+}
+
+/// Open a link with the system default handler
+fn open_link_with_system(url: &str) {
+    eprintln!("[EXTERNAL LINK] Opening: {}", url);
+
+    #[cfg(target_os = "linux")]
+    {
+        let _ = Command::new("xdg-open").arg(url).spawn();
     }
 
-    None
+    #[cfg(target_os = "macos")]
+    {
+        let _ = Command::new("open").arg(url).spawn();
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let _ = Command::new("cmd").args(["/C", "start", "", url]).spawn();
+    }
 }
 
 /// Open a file with the system default application
@@ -439,133 +712,252 @@ fn open_with_system(path: &PathBuf) {
     }
 }
 
-/// Get downloads directory based on platform
-fn get_downloads_dir() -> PathBuf {
-    dirs::download_dir().unwrap_or_else(|| {
-        #[cfg(target_os = "linux")]
-        return PathBuf::from("~/Downloads");
+/// Get the downloads directory, creating it if needed
+fn get_downloads_dir(config: &Mutex<Config>) -> PathBuf {
+    let configured = config.lock().ok().and_then(|cfg| cfg.download_dir.clone());
+    let dir = downloads::resolve_download_dir(configured.as_deref());
+
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("[DOWNLOAD] Cannot create {:?}: {}", dir, e);
+    }
 
-        #[cfg(not(target_os = "linux"))]
-        return PathBuf::from(".");
-    })
+    dir
 }
 
-/// Handle file opening based on user preferences
-fn handle_file_open(path: &PathBuf, config: &mut Config) {
-    let extension = path
-        .extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("")
-        .to_lowercase();
+/// Open a file or link with the given browser, returning false if it could
+/// not be launched
+fn open_with_browser(browser: &Browser, target: &str, config: &Mutex<Config>) -> bool {
+    eprintln!(
+        "[BROWSER] Opening {:?} with {}",
+        target,
+        browser.display_name()
+    );
 
-    match extension.as_str() {
-        "pdf" => {
-            let installed = detect_installed_browsers();
-            let browsers: Vec<_> = installed
-                .iter()
-                .chain(std::iter::once(&Browser::System))
-                .map(|b| b.display_name())
-                .collect();
+    #[cfg(target_os = "linux")]
+    {
+        let custom = config
+            .lock()
+            .map(|cfg| cfg.custom_browsers.clone())
+            .unwrap_or_default();
+        let Some(args) = browser.command_line(target, &custom) else {
+            eprintln!("[BROWSER] {:?} is not available", browser);
+            return false;
+        };
+        let Some((program, rest)) = args.split_first() else {
+            return false;
+        };
+        Command::new(program).args(rest).spawn().is_ok()
+    }
 
-            if let Some(idx) =
-                show_selection_dialog("Open PDF", "Select browser to open PDF files:", &browsers)
-            {
-                let selected = if idx < installed.len() {
-                    installed[idx]
-                } else {
-                    Browser::System
-                };
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (browser, config);
+        open_with_system(&PathBuf::from(target));
+        true
+    }
+}
 
-                config.pdf_browser = Some(selected);
+/// Open a document with the chosen handler
+fn open_with_doc_handler(handler: DocHandler, path: &PathBuf) {
+    if matches!(handler, DocHandler::GoogleDocs) {
+        let google_docs_url = "https://docs.google.com/document/upload";
+        let _ = Command::new("xdg-open").arg(google_docs_url).spawn();
+    } else {
+        open_with_system(path);
+    }
+}
 
-                #[cfg(target_os = "linux")]
-                {
-                    let _ = Command::new(selected.command()).arg(path).spawn();
-                }
+/// Run a user-configured command template on a file
+fn run_command_template(template: &str, path: &Path) {
+    let args = rules::expand_command(template, path);
+    let Some((program, rest)) = args.split_first() else {
+        eprintln!("[RULES] Empty command template");
+        return;
+    };
 
-                #[cfg(not(target_os = "linux"))]
-                {
-                    open_with_system(path);
-                }
-            }
-        }
+    eprintln!("[RULES] Running: {:?}", args);
+    if let Err(e) = Command::new(program).args(rest).spawn() {
+        eprintln!("[RULES] Failed to run {}: {}", program, e);
+    }
+}
 
-        "odt" | "odp" | "ods" | "doc" | "docx" | "xls" | "xlsx" | "ppt" | "pptx" => {
-            let handlers = [DocHandler::GoogleDocs, DocHandler::LocalApp];
-            let options: Vec<&str> = handlers.iter().map(|h| h.display_name()).collect();
-
-            if let Some(idx) = show_selection_dialog(
-                "Open Document",
-                "How would you like to open this document?",
-                &options,
-            ) {
-                if let Some(handler) = handlers.get(idx) {
-                    config.doc_handler = Some(*handler);
-
-                    if matches!(handler, DocHandler::GoogleDocs) {
-                        let google_docs_url = "https://docs.google.com/document/upload";
-                        let _ = Command::new("xdg-open").arg(google_docs_url).spawn();
-                    } else {
-                        open_with_system(path);
-                    }
-                }
-            }
+/// Apply a change to the shared config and persist it right away
+fn update_config(config: &Mutex<Config>, change: impl FnOnce(&mut Config)) {
+    if let Ok(mut cfg) = config.lock() {
+        change(&mut cfg);
+        cfg.save();
+    }
+}
+
+/// Open a PDF with the saved browser, or ask the user for one.
+///
+/// The config lock is never held while a dialog is open, so other downloads
+/// can be processed in the meantime.
+fn open_pdf(path: &PathBuf, config: &Mutex<Config>, chooser: &Chooser) {
+    let saved = config.lock().ok().and_then(|cfg| cfg.pdf_browser.clone());
+
+    let target = path.to_string_lossy();
+
+    if let Some(browser) = saved {
+        if open_with_browser(&browser, &target, config) {
+            return;
         }
+        // Saved browser is gone (uninstalled?) - forget it and ask again
+        eprintln!(
+            "[CONFIG] Saved PDF browser {} failed to launch, resetting",
+            browser.display_name()
+        );
+        update_config(config, |cfg| cfg.pdf_browser = None);
+    }
+
+    let installed = browser_choices(config);
+    let system = Browser::System.display_name();
+    let browsers: Vec<&str> = installed
+        .iter()
+        .map(|(_, name)| name.as_str())
+        .chain(std::iter::once(system.as_str()))
+        .collect();
 
-        "mp4" | "webm" | "mov" | "mkv" | "avi" | "mp3" | "wav" | "ogg" | "flac" | "jpg"
-        | "jpeg" | "png" | "gif" | "webp" | "bmp" | "zip" | "rar" | "7z" | "tar" | "gz" | "xz"
-        | "txt" | "rtf" | "csv" => {
-            open_with_system(path);
+    if let Some(choice) = chooser.choose("Open PDF", "Select browser to open PDF files:", &browsers)
+    {
+        let selected = installed
+            .get(choice.index)
+            .map(|(browser, _)| browser.clone())
+            .unwrap_or(Browser::System);
+
+        if choice.remember {
+            update_config(config, |cfg| cfg.pdf_browser = Some(selected.clone()));
         }
 
-        _ => {
-            open_with_system(path);
+        open_with_browser(&selected, &target, config);
+    }
+}
+
+/// Open a document with the saved handler, or ask the user for one
+fn open_document(path: &PathBuf, config: &Mutex<Config>, chooser: &Chooser) {
+    let saved = config.lock().ok().and_then(|cfg| cfg.doc_handler);
+
+    if let Some(handler) = saved {
+        open_with_doc_handler(handler, path);
+        return;
+    }
+
+    let handlers = [DocHandler::GoogleDocs, DocHandler::LocalApp];
+    let options: Vec<&str> = handlers.iter().map(|h| h.display_name()).collect();
+
+    if let Some(choice) = chooser.choose(
+        "Open Document",
+        "How would you like to open this document?",
+        &options,
+    ) {
+        if let Some(handler) = handlers.get(choice.index) {
+            if choice.remember {
+                update_config(config, |cfg| cfg.doc_handler = Some(*handler));
+            }
+
+            open_with_doc_handler(*handler, path);
         }
     }
 }
 
-/// Fix file extension based on content sniffing
-fn fix_file_extension(path: &PathBuf) -> PathBuf {
-    if !path.exists() {
-        return path.clone();
+/// Handle file opening based on the configured open rules, returning the
+/// action that was taken
+fn handle_file_open(path: &PathBuf, config: &Mutex<Config>, chooser: &Chooser) -> OpenAction {
+    let mut facts = FileFacts::from_path(path);
+    if let Some(kind) = sniff::sniff_file(path) {
+        facts.sniffed = Some(kind.extension().to_string());
+        facts.mime = Some(kind.mime().to_string());
     }
 
-    let file = File::open(path);
-    if file.is_err() {
-        return path.clone();
+    let action = config
+        .lock()
+        .ok()
+        .and_then(|cfg| rules::find_action(&cfg.open_rules, &facts).cloned())
+        .unwrap_or(OpenAction::System);
+
+    eprintln!("[RULES] {:?} -> {:?}", path, action);
+
+    let launches = !matches!(action, OpenAction::Reveal | OpenAction::Nothing);
+    if launches && !confirm_dangerous_open(path, config, chooser) {
+        return OpenAction::Nothing;
     }
-    let mut file = file.unwrap();
 
-    let mut buffer = [0u8; 16];
-    let bytes_read = file.read(&mut buffer);
-    if bytes_read.is_err() {
-        return path.clone();
+    match &action {
+        OpenAction::System => open_with_system(path),
+        OpenAction::Browser(browser) => {
+            open_with_browser(browser, &path.to_string_lossy(), config);
+        }
+        OpenAction::Command(template) => run_command_template(template, path),
+        OpenAction::Reveal => desktop::reveal_in_folder(path),
+        OpenAction::Nothing => {}
+        OpenAction::AskBrowser => open_pdf(path, config, chooser),
+        OpenAction::AskDocHandler => open_document(path, config, chooser),
     }
-    let bytes_read = bytes_read.unwrap();
 
-    let new_ext = match bytes_read {
-        8 => match &buffer[0..8] {
-            b"\x89\x50\x4E\x47\x0D\x0A\x1A\x0A" => Some("png"),
-            _ => None,
-        },
-        4 => match &buffer[0..4] {
-            b"\xFF\xD8\xFF\xE0" | b"\xFF\xD8\xFF\xE1" | b"\xFF\xD8\xFF\xE2"
-            | b"\xFF\xD8\xFF\xE3" | b"\xFF\xD8\xFF\xE8" => Some("jpg"),
-            b"RIFF" => Some("webp"),
-            b"GIF8" => Some("gif"),
-            _ => None,
-        },
-        _ => None,
+    action
+}
+
+/// Check a file about to be opened against the dangerous-file guard; returns
+/// whether opening it may go ahead
+fn confirm_dangerous_open(path: &PathBuf, config: &Mutex<Config>, chooser: &Chooser) -> bool {
+    let (policy, denylist) = match config.lock() {
+        Ok(cfg) => (cfg.dangerous_file_policy, cfg.dangerous_extensions.clone()),
+        Err(_) => (DangerousFilePolicy::Ask, guard::default_denylist()),
+    };
+    if policy == DangerousFilePolicy::Allow {
+        return true;
+    }
+
+    let Some(danger) = guard::check(path, &denylist) else {
+        return true;
     };
+    eprintln!("[GUARD] {:?} {}", path, danger.describe());
 
-    if let Some(ext) = new_ext {
-        let mut new_path = path.clone();
-        new_path.set_extension(ext);
-        if new_path != *path {
-            eprintln!("[FILE] Fixing extension: {:?} -> {:?}", path, new_path);
-            let _ = std::fs::rename(path, &new_path);
-            return new_path;
+    if policy == DangerousFilePolicy::Keep {
+        return false;
+    }
+
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let text = format!(
+        "\"{}\" {}.\nOpening it could run a program on your computer. \
+         It has been saved, but not opened.",
+        name,
+        danger.describe()
+    );
+    let options = ["Keep it without opening", "Show in folder", "Open anyway"];
+
+    match chooser.confirm("Potentially dangerous file", &text, &options, "OK") {
+        Some(1) => {
+            desktop::reveal_in_folder(path);
+            false
+        }
+        Some(2) => {
+            eprintln!("[GUARD] Opening {:?} at the user's request", path);
+            true
         }
+        _ => false,
+    }
+}
+
+/// Fix file extension based on content sniffing
+fn fix_file_extension(path: &PathBuf) -> PathBuf {
+    let Some(extension) = sniff::corrected_extension(path) else {
+        return path.clone();
+    };
+
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("download");
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let filename = format!("{}.{}", stem, extension);
+    let new_path = downloads::unique_path(dir, &filename, |_| false);
+    eprintln!("[FILE] Fixing extension: {:?} -> {:?}", path, new_path);
+    if std::fs::rename(path, &new_path).is_ok() {
+        return new_path;
     }
 
     path.clone()
@@ -581,10 +973,15 @@ fn load_icon() -> Option<Icon> {
 }
 
 #[cfg(target_os = "linux")]
-fn setup_webview_for_calls(webview: &webkit2gtk::WebView) {
+fn setup_webview_for_calls(
+    webview: &webkit2gtk::WebView,
+    prompts: std::rc::Rc<permissions::PermissionPrompts>,
+    devtools: bool,
+    cookie_path: &Path,
+) {
     use webkit2gtk::{
-        CookieAcceptPolicy, CookieManagerExt, CookiePersistentStorage, PermissionRequestExt,
-        SettingsExt, WebContextExt, WebViewExt,
+        CookieAcceptPolicy, CookieManagerExt, CookiePersistentStorage, SettingsExt, WebContextExt,
+        WebViewExt,
     };
 
     if let Some(settings) = webview.settings() {
@@ -616,8 +1013,8 @@ fn setup_webview_for_calls(webview: &webkit2gtk::WebView) {
         settings.set_enable_html5_database(true);
         settings.set_enable_offline_web_application_cache(true);
 
-        // ========== DEVELOPER TOOLS (for debugging, can disable for prod) ==========
-        settings.set_enable_developer_extras(true);
+        // ========== DEVELOPER TOOLS (off unless asked for, see Policy::devtools) ==========
+        settings.set_enable_developer_extras(devtools);
         settings.set_allow_modal_dialogs(true);
         settings.set_enable_resizable_text_areas(true);
         settings.set_enable_fullscreen(true);
@@ -629,41 +1026,466 @@ fn setup_webview_for_calls(webview: &webkit2gtk::WebView) {
     if let Some(context) = webview.context() {
         let cookie_manager = context.cookie_manager().unwrap();
 
-        let cookie_path = dirs::data_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("whatsapp-desktop")
-            .join("cookies.txt");
-
-        if let Some(parent) = cookie_path.parent() {
-            let _ = std::fs::create_dir_all(parent);
-        }
-
         cookie_manager.set_persistent_storage(
-            cookie_path.to_str().unwrap_or("cookies.txt"),
-            CookiePersistentStorage::Text,
+            cookie_path.to_str().unwrap_or("cookies.sqlite"),
+            CookiePersistentStorage::Sqlite,
         );
         cookie_manager.set_accept_policy(CookieAcceptPolicy::Always);
 
         eprintln!("[COOKIES] Persistent storage at: {:?}", cookie_path);
     }
 
-    webview.connect_permission_request(|_webview, permission_request| {
-        eprintln!("[PERMISSION] Request received - auto-granting");
-        permission_request.allow();
+    // Decided by the permission policy, possibly after asking
+    webview.connect_permission_request(move |_webview, permission_request| {
+        prompts.handle(permission_request);
         true
     });
 }
 
+/// Keep the main webview on `Config::navigation_allowlist`. WebKit only
+/// tells whether a navigation replaces the page or loads into an embedded
+/// frame once its response arrives, so that is where it is decided, see
+/// `links::decide_navigation`. Links the user clicks are remembered on the
+/// way, since only those may be opened externally.
+#[cfg(target_os = "linux")]
+fn setup_navigation(
+    webview: &webkit2gtk::WebView,
+    config: Arc<Mutex<Config>>,
+    proxy: tao::event_loop::EventLoopProxy<UserEvent>,
+) {
+    use gtk::prelude::*;
+    use links::NavigationVerdict;
+    use std::cell::RefCell;
+    use webkit2gtk::{
+        NavigationPolicyDecision, NavigationPolicyDecisionExt, NavigationType, PolicyDecisionExt,
+        PolicyDecisionType, ResponsePolicyDecision, ResponsePolicyDecisionExt, URIRequestExt,
+        WebViewExt,
+    };
+
+    let clicked: RefCell<Option<String>> = RefCell::default();
+
+    webview.connect_decide_policy(
+        move |_webview, decision, decision_type| match decision_type {
+            PolicyDecisionType::NavigationAction => {
+                let Some(action) = decision
+                    .dynamic_cast_ref::<NavigationPolicyDecision>()
+                    .and_then(|navigation| navigation.navigation_action())
+                else {
+                    return false;
+                };
+                if action.is_user_gesture()
+                    && action.navigation_type() == NavigationType::LinkClicked
+                {
+                    let url = action.request().and_then(|request| request.uri());
+                    *clicked.borrow_mut() = url.map(|uri| uri.to_string());
+                }
+                false
+            }
+            PolicyDecisionType::Response => {
+                let Some(response) = decision.dynamic_cast_ref::<ResponsePolicyDecision>() else {
+                    return false;
+                };
+                if !response.is_main_frame_main_resource() {
+                    return false;
+                }
+                let url = response
+                    .request()
+                    .and_then(|request| request.uri())
+                    .map(|uri| uri.to_string())
+                    .unwrap_or_default();
+                let was_clicked = clicked.take().is_some_and(|link| link == url);
+
+                let verdict = match config.lock() {
+                    Ok(cfg) => links::decide_navigation(
+                        &cfg.navigation_allowlist,
+                        cfg.blocked_navigation,
+                        &url,
+                        true,
+                        was_clicked,
+                    ),
+                    Err(_) => NavigationVerdict::Block,
+                };
+                match verdict {
+                    NavigationVerdict::Allow => return false,
+                    NavigationVerdict::OpenExternally => {
+                        eprintln!("[NAV] Not in allowlist, opening externally: {}", url);
+                        let _ = proxy.send_event(UserEvent::OpenLink(url));
+                    }
+                    NavigationVerdict::Block => {
+                        eprintln!("[NAV] Not in allowlist, blocked: {}", url);
+                    }
+                }
+                decision.ignore();
+                true
+            }
+            _ => false,
+        },
+    );
+}
+
+/// Save WebKit downloads under their suggested filename without overwriting
+/// anything, refuse those that are too large for the limit or the disk, show
+/// their progress, and queue them for post-processing once they finished
+#[cfg(target_os = "linux")]
+fn setup_downloads(
+    webview: &webkit2gtk::WebView,
+    config: Arc<Mutex<Config>>,
+    processor: PostProcessor,
+    proxy: tao::event_loop::EventLoopProxy<UserEvent>,
+    tracker: std::rc::Rc<progress::DownloadTracker>,
+) {
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashSet;
+    use std::rc::Rc;
+    use webkit2gtk::{DownloadExt, URIRequestExt, URIResponseExt, WebContextExt, WebViewExt};
+
+    const MB: u64 = 1024 * 1024;
+
+    let Some(context) = webview.context() else {
+        return;
+    };
+
+    // Destinations handed out to downloads that haven't finished yet
+    let pending: Rc<RefCell<HashSet<PathBuf>>> = Rc::default();
+
+    context.connect_download_started(move |_context, download| {
+        let url = download
+            .request()
+            .and_then(|req| req.uri())
+            .map(|uri| uri.to_string())
+            .unwrap_or_default();
+        let id = tracker.start(download, &url);
+        let destination: Rc<RefCell<Option<PathBuf>>> = Rc::default();
+        let failed = Rc::new(Cell::new(false));
+        // Size limit in bytes, read when the download starts
+        let max_size: Rc<Cell<Option<u64>>> = Rc::default();
+
+        download.connect_decide_destination({
+            let url = url.clone();
+            let config = config.clone();
+            let proxy = proxy.clone();
+            let pending = pending.clone();
+            let destination = destination.clone();
+            let failed = failed.clone();
+            let max_size = max_size.clone();
+            let tracker = tracker.clone();
+            move |download, suggested_filename| {
+                let filename = downloads::choose_filename(Some(suggested_filename), &url)
+                    .unwrap_or_else(downloads::fallback_filename);
+                let dir = get_downloads_dir(&config);
+
+                let (max, reserve) = config
+                    .lock()
+                    .map(|cfg| {
+                        (
+                            cfg.max_download_mb.map(|mb| mb.saturating_mul(MB)),
+                            cfg.min_free_space_mb.saturating_mul(MB),
+                        )
+                    })
+                    .unwrap_or_default();
+                max_size.set(max);
+
+                // Content-Length, when the server sent one
+                let size = download
+                    .response()
+                    .map(|response| response.content_length())
+                    .filter(|&len| len > 0);
+                let free = downloads::free_space(&dir);
+
+                if let Err(rejection) = downloads::check_limits(size, free, max, reserve) {
+                    eprintln!("[DOWNLOAD] Rejected: {} ({:?})", url, rejection);
+                    failed.set(true);
+                    tracker.remove(id);
+                    let _ =
+                        proxy.send_event(UserEvent::DownloadRejected(rejection.message(&filename)));
+                    download.cancel();
+                    return true;
+                }
+
+                let dest =
+                    downloads::unique_path(&dir, &filename, |path| pending.borrow().contains(path));
+                eprintln!("[DOWNLOAD] Starting: {} -> {:?}", url, dest);
+
+                download.set_destination(&dest.to_string_lossy());
+                if let Some(name) = dest.file_name() {
+                    tracker.set_name(id, &name.to_string_lossy());
+                }
+                pending.borrow_mut().insert(dest.clone());
+                *destination.borrow_mut() = Some(dest);
+                true
+            }
+        });
+
+        download.connect_received_data({
+            let url = url.clone();
+            let proxy = proxy.clone();
+            let destination = destination.clone();
+            let failed = failed.clone();
+            let tracker = tracker.clone();
+            move |download, _length| {
+                let received = download.received_data_length();
+                let total = download
+                    .response()
+                    .map(|response| response.content_length())
+                    .filter(|&len| len > 0);
+                tracker.progress(id, received, total);
+
+                // Servers don't always announce the size; enforce the limit as data arrives
+                let (Some(max), false) = (max_size.get(), failed.get()) else {
+                    return;
+                };
+                if received <= max {
+                    return;
+                }
+
+                let rejection = downloads::Rejection::TooLarge {
+                    size: received,
+                    max,
+                };
+                eprintln!("[DOWNLOAD] Rejected: {} ({:?})", url, rejection);
+                failed.set(true);
+                tracker.remove(id);
+                let filename = destination
+                    .borrow()
+                    .as_ref()
+                    .and_then(|d| d.file_name())
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let _ = proxy.send_event(UserEvent::DownloadRejected(rejection.message(&filename)));
+                download.cancel();
+            }
+        });
+
+        download.connect_failed({
+            let url = url.clone();
+            let failed = failed.clone();
+            let tracker = tracker.clone();
+            move |_download, error| {
+                // Already reported if we cancelled it ourselves
+                if !failed.replace(true) {
+                    eprintln!("[DOWNLOAD] Failed: {} ({})", url, error);
+                    tracker.fail(id, &error.to_string());
+                }
+            }
+        });
+
+        // "finished" is emitted after "failed" too
+        download.connect_finished({
+            let pending = pending.clone();
+            let processor = processor.clone();
+            let tracker = tracker.clone();
+            move |_download| {
+                tracker.finish(id);
+                let Some(file_path) = destination.borrow_mut().take() else {
+                    return;
+                };
+                pending.borrow_mut().remove(&file_path);
+
+                if failed.get() {
+                    return;
+                }
+
+                eprintln!("[DOWNLOAD] Completed: {:?}", file_path);
+                processor.submit(DownloadJob {
+                    url: url.clone(),
+                    path: file_path,
+                });
+            }
+        });
+    });
+}
+
+/// Show a short in-page notice, styled like the mute indicator
+fn show_toast(webview: &wry::WebView, message: &str, color: &str) {
+    let script = format!(
+        r#"
+        (function() {{
+            const toast = document.createElement('div');
+            toast.style.cssText = 'position:fixed;bottom:20px;left:50%;transform:translateX(-50%);background:{};color:white;padding:8px 16px;border-radius:20px;z-index:999999;font-size:13px;box-shadow:0 2px 10px rgba(0,0,0,0.2);transition:opacity 0.3s;';
+            toast.textContent = {};
+            document.body.appendChild(toast);
+            setTimeout(() => {{
+                toast.style.opacity = '0';
+                setTimeout(() => toast.remove(), 300);
+            }}, 3000);
+        }})();
+        "#,
+        color,
+        serde_json::to_string(message).unwrap_or_default()
+    );
+    let _ = webview.evaluate_script(&script);
+}
+
+/// Open (or refresh) the Ctrl+J download history overlay
+fn show_history(webview: &wry::WebView, history: &Mutex<History>) {
+    let Ok(items) = history.lock().map(|h| h.overlay_json()) else {
+        return;
+    };
+    let script = format!(
+        "window.__downloadHistory && window.__downloadHistory.show({});",
+        items
+    );
+    let _ = webview.evaluate_script(&script);
+}
+
+/// Open a link in a secondary window with its own, empty session
+fn open_link_window(
+    target: &EventLoopWindowTarget<UserEvent>,
+    url: &str,
+) -> Result<(Window, wry::WebView), Box<dyn std::error::Error>> {
+    let mut window_builder = WindowBuilder::new()
+        .with_title(links::host_of(url))
+        .with_inner_size(tao::dpi::LogicalSize::new(1000.0, 700.0));
+
+    if let Some(icon) = load_icon() {
+        window_builder = window_builder.with_window_icon(Some(icon));
+    }
+
+    let window = window_builder.build(target)?;
+    let builder = WebViewBuilder::new().with_url(url);
+
+    #[cfg(target_os = "linux")]
+    let webview = {
+        use tao::platform::unix::WindowExtUnix;
+        use wry::WebViewBuilderExtUnix;
+        builder.build_gtk(window.default_vbox().unwrap())?
+    };
+
+    #[cfg(not(target_os = "linux"))]
+    let webview = builder.build(&window)?;
+
+    Ok((window, webview))
+}
+
+/// Send a link wherever its rule says
+fn launch_link(
+    url: &str,
+    target: LinkTarget,
+    config: &Mutex<Config>,
+    webview: &wry::WebView,
+    event_loop_target: &EventLoopWindowTarget<UserEvent>,
+    link_windows: &mut HashMap<WindowId, (wry::WebView, Window)>,
+) {
+    match target {
+        LinkTarget::System => open_link_with_system(url),
+        LinkTarget::Browser(browser) => {
+            if !open_with_browser(&browser, url, config) {
+                open_link_with_system(url);
+            }
+        }
+        LinkTarget::Window => match open_link_window(event_loop_target, url) {
+            Ok((link_window, link_webview)) => {
+                link_windows.insert(link_window.id(), (link_webview, link_window));
+            }
+            Err(e) => {
+                eprintln!("[LINKS] Failed to open window: {}", e);
+                open_link_with_system(url);
+            }
+        },
+        LinkTarget::Clipboard => {
+            if copy_to_clipboard(url) {
+                show_toast(webview, "Link copied to clipboard", "#25D366");
+            }
+        }
+        LinkTarget::Block => {
+            let message = format!("Blocked link to {}", links::host_of(url));
+            show_toast(webview, &message, "#ff6b6b");
+        }
+    }
+}
+
+/// Ask for confirmation before opening a flagged link. The chooser blocks, so
+/// it runs on a worker thread; the answer comes back as
+/// `UserEvent::LinkDecided`.
+fn ask_link_warning(
+    chooser: &Chooser,
+    proxy: tao::event_loop::EventLoopProxy<UserEvent>,
+    id: u64,
+    url: &str,
+    warnings: &[LinkWarning],
+) {
+    let reasons: Vec<String> = warnings
+        .iter()
+        .map(|warning| format!("• {}", warning.describe()))
+        .collect();
+    let text = format!("{}\n\n{}", linkcheck::decode(url), reasons.join("\n"));
+    let chooser = chooser.clone();
+
+    std::thread::spawn(move || {
+        // Cancel comes first, so it is what a careless Enter picks
+        let options = ["Cancel", "Open anyway"];
+        let decision = match chooser.confirm("This link may not be safe", &text, &options, "OK") {
+            Some(1) => LinkDecision::Open(id),
+            _ => LinkDecision::Cancel(id),
+        };
+        let _ = proxy.send_event(UserEvent::LinkDecided(decision));
+    });
+}
+
+/// Put text on the clipboard, returning false where that isn't supported
+fn copy_to_clipboard(text: &str) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        let Some(display) = gdk::Display::default() else {
+            return false;
+        };
+        let Some(clipboard) = gtk::Clipboard::default(&display) else {
+            return false;
+        };
+        clipboard.set_text(text);
+        true
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = text;
+        false
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = std::sync::Arc::new(std::sync::Mutex::new(Config::load()));
+    let config = Arc::new(Mutex::new(Config::load()));
 
-    if let Ok(cfg) = config.lock() {
+    if let Ok(mut cfg) = config.lock() {
+        if std::env::args().any(|arg| arg == "--reset-preferences") {
+            eprintln!("[CONFIG] Resetting saved PDF browser and document handler");
+            cfg.reset_open_preferences();
+        }
         cfg.save();
     }
 
-    let config_clone = config.clone();
+    let policy = Policy::load();
+    let devtools = policy.devtools(
+        std::env::args().any(|arg| arg == "--devtools")
+            || config.lock().map(|cfg| cfg.devtools).unwrap_or(false),
+    );
+    if devtools {
+        eprintln!("[INFO] Developer tools enabled");
+    }
 
-    let event_loop = EventLoop::new();
+    let event_loop = EventLoopBuilder::<UserEvent>::with_user_event().build();
+
+    // Before any window, a passphrase may have to be asked for
+    let encryption = config
+        .lock()
+        .map(|cfg| cfg.profile_encryption)
+        .unwrap_or_default();
+    let mut profile = Profile::open(encryption).map_err(|e| {
+        eprintln!("[PROFILE] Can't open the profile: {}", e);
+        e
+    })?;
+    let dialog_backend = config
+        .lock()
+        .map(|cfg| cfg.dialog_backend)
+        .unwrap_or_default();
+    let chooser = Chooser::new(event_loop.create_proxy(), dialog_backend);
+    let history = Arc::new(Mutex::new(History::load()));
+    let processor = PostProcessor::spawn(
+        config.clone(),
+        history.clone(),
+        chooser.clone(),
+        event_loop.create_proxy(),
+    );
 
     let mut window_builder = WindowBuilder::new()
         .with_title("WhatsApp")
@@ -676,104 +1498,87 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let window = window_builder.build(&event_loop)?;
 
-    let data_dir = dirs::data_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("whatsapp-desktop")
-        .join("webdata");
-
-    if let Err(e) = fs::create_dir_all(&data_dir) {
-        eprintln!("[WARN] Failed to create data directory: {}", e);
-    }
-
-    eprintln!("[INFO] WebView data directory: {:?}", data_dir);
+    eprintln!("[INFO] WebView data directory: {:?}", profile.webdata);
 
-    let mut web_context = WebContext::new(Some(data_dir));
+    let mut web_context = WebContext::new(Some(profile.webdata.clone()));
 
     let builder = WebViewBuilder::with_web_context(&mut web_context)
         .with_user_agent(USER_AGENT)
         .with_initialization_script(SPOOF_SCRIPT)
         .with_initialization_script(NOTIFICATION_MUTE_SCRIPT)
+        .with_initialization_script(history::HISTORY_OVERLAY_SCRIPT)
+        .with_initialization_script(progress::PROGRESS_OVERLAY_SCRIPT)
+        .with_initialization_script(linkcheck::LINK_WARNING_SCRIPT)
+        .with_initialization_script(capture::CAPTURE_BADGE_SCRIPT)
         .with_autoplay(true)
         .with_url("https://web.whatsapp.com")
-        .with_navigation_handler(|url| {
-            let dominated = url.starts_with("https://web.whatsapp.com")
-                || url.starts_with("blob:")
-                || url.starts_with("data:")
-                || url.starts_with("https://mmg.whatsapp.net")
-                || url.starts_with("https://static.whatsapp.net")
-                || url.starts_with("https://pps.whatsapp.net");
-
-            if !dominated {
-                eprintln!("[NAV] URL: {}", url);
+        .with_ipc_handler({
+            let proxy = event_loop.create_proxy();
+            move |request| match serde_json::from_str::<IpcMessage>(request.body()) {
+                Ok(message) => {
+                    let _ = proxy.send_event(UserEvent::Ipc(message));
+                }
+                Err(e) => eprintln!("[IPC] Ignoring {:?}: {}", request.body(), e),
             }
-            true
         })
-        .with_new_window_req_handler(|url| {
-            eprintln!("[EXTERNAL LINK] Opening: {}", url);
-
-            #[cfg(target_os = "linux")]
-            {
-                let _ = Command::new("xdg-open").arg(&url).spawn();
-            }
-
-            #[cfg(target_os = "macos")]
-            {
-                let _ = Command::new("open").arg(&url).spawn();
+        .with_new_window_req_handler({
+            let proxy = event_loop.create_proxy();
+            move |url| {
+                let _ = proxy.send_event(UserEvent::OpenLink(url));
+                false
             }
+        })
+        .with_devtools(devtools);
 
-            #[cfg(target_os = "windows")]
-            {
-                let _ = Command::new("cmd").args(["/C", "start", "", &url]).spawn();
+    // Linux checks navigations in WebKit's decide-policy, see setup_navigation().
+    // wry's handler can't tell embedded frames from the page, so elsewhere a
+    // navigation outside the allowlist is only ever blocked.
+    #[cfg(not(target_os = "linux"))]
+    let builder = builder.with_navigation_handler({
+        let config = config.clone();
+        move |url| {
+            let allowed = config
+                .lock()
+                .map(|cfg| links::navigation_allowed(&cfg.navigation_allowlist, &url))
+                .unwrap_or(false);
+            if !allowed {
+                eprintln!("[NAV] Not in allowlist, blocked: {}", url);
             }
+            allowed
+        }
+    });
 
-            false
-        })
+    // Linux hooks WebKit's download signals directly, see setup_downloads()
+    #[cfg(not(target_os = "linux"))]
+    let downloads_config = config.clone();
+    #[cfg(not(target_os = "linux"))]
+    let builder = builder
         .with_download_started_handler(move |url, download_path| {
-            let downloads_dir = get_downloads_dir();
-
-            let filename = url
-                .split('/')
-                .last()
-                .and_then(|s| s.split('?').next())
-                .filter(|s| !s.is_empty() && s.len() < 255)
-                .unwrap_or("whatsapp_download");
-
-            let clean_filename: String = filename
-                .chars()
-                .map(|c| {
-                    if c.is_alphanumeric() || c == '.' || c == '-' || c == '_' {
-                        c
-                    } else {
-                        '_'
-                    }
-                })
-                .collect();
-
-            let dest = downloads_dir.join(&clean_filename);
+            let filename =
+                downloads::choose_filename(None, &url).unwrap_or_else(downloads::fallback_filename);
+            let dest =
+                downloads::unique_path(&get_downloads_dir(&downloads_config), &filename, |_| false);
             eprintln!("[DOWNLOAD] Starting: {} -> {:?}", url, dest);
 
             *download_path = dest;
             true
         })
-        .with_download_completed_handler(move |_url, path, success| {
+        .with_download_completed_handler(move |url, path, success| {
             if success {
                 if let Some(file_path) = path {
                     eprintln!("[DOWNLOAD] Completed: {:?}", file_path);
-
-                    let fixed_path = fix_file_extension(&file_path);
-
-                    if let Ok(mut cfg) = config_clone.lock() {
-                        handle_file_open(&fixed_path, &mut cfg);
-                    }
+                    processor.submit(DownloadJob {
+                        url,
+                        path: file_path,
+                    });
                 }
             } else {
                 eprintln!("[DOWNLOAD] Failed: {:?}", path);
             }
-        })
-        .with_devtools(true);
+        });
 
     #[cfg(target_os = "linux")]
-    let _webview = {
+    let (webview, download_tracker, permission_prompts) = {
         use gtk::prelude::*;
         use tao::platform::unix::WindowExtUnix;
         use webkit2gtk::WebViewExt as WebkitWebViewExt;
@@ -783,7 +1588,28 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let webview = builder.build_gtk(vbox)?;
 
         let gtk_webview = webview.webview();
-        setup_webview_for_calls(&gtk_webview);
+        let permission_prompts = permissions::PermissionPrompts::new(
+            gtk_webview.clone(),
+            config.clone(),
+            chooser.clone(),
+            event_loop.create_proxy(),
+        );
+        setup_webview_for_calls(
+            &gtk_webview,
+            permission_prompts.clone(),
+            devtools,
+            &profile.cookies,
+        );
+        setup_navigation(&gtk_webview, config.clone(), event_loop.create_proxy());
+        capture::watch(&gtk_webview, event_loop.create_proxy());
+        let download_tracker = progress::DownloadTracker::new(gtk_webview.clone());
+        setup_downloads(
+            &gtk_webview,
+            config.clone(),
+            processor,
+            event_loop.create_proxy(),
+            download_tracker.clone(),
+        );
 
         let webview_for_paste = gtk_webview.clone();
 
@@ -832,6 +1658,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 return glib::Propagation::Stop;
             }
 
+            // Check for Ctrl+Shift+K (stop all capture)
+            if state.contains(gdk::ModifierType::CONTROL_MASK)
+                && state.contains(gdk::ModifierType::SHIFT_MASK)
+                && (keyval == gdk::keys::constants::k || keyval == gdk::keys::constants::K)
+            {
+                capture::stop_all(&gtk_webview);
+                return glib::Propagation::Stop;
+            }
+
             // Check for Ctrl+V
             if state.contains(gdk::ModifierType::CONTROL_MASK)
                 && (keyval == gdk::keys::constants::v || keyval == gdk::keys::constants::V)
@@ -949,26 +1784,302 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             glib::Propagation::Proceed
         });
 
-        webview
+        (webview, download_tracker, permission_prompts)
     };
 
     #[cfg(not(target_os = "linux"))]
-    let _webview = builder.build(&window)?;
+    let webview = builder.build(&window)?;
 
     eprintln!("[INFO] WhatsApp Desktop started");
-    eprintln!("[INFO] Downloads saved to: {:?}", get_downloads_dir());
+    eprintln!(
+        "[INFO] Downloads saved to: {:?}",
+        get_downloads_dir(&config)
+    );
     eprintln!("[INFO] Config stored at: {:?}", Config::config_path());
 
-    event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Wait;
+    let main_window_id = window.id();
+    // The webview is dropped before its window
+    let mut link_windows: HashMap<WindowId, (wry::WebView, Window)> = HashMap::new();
+    // Text of the last clicked link, and flagged links awaiting confirmation
+    let mut last_clicked: Option<(String, String)> = None;
+    let mut pending_links: HashMap<u64, (String, LinkTarget)> = HashMap::new();
+    let mut next_link_id: u64 = 0;
+    let link_proxy = event_loop.create_proxy();
+    let history_proxy = event_loop.create_proxy();
+
+    let retention_proxy = event_loop.create_proxy();
+    let mut next_retention = retention::next_run(retention::spawn(
+        &config,
+        history.clone(),
+        retention_proxy.clone(),
+    ));
+
+    event_loop.run(move |event, event_loop_target, control_flow| {
+        *control_flow = ControlFlow::WaitUntil(next_retention);
 
         match event {
             Event::WindowEvent {
+                window_id,
                 event: WindowEvent::CloseRequested,
                 ..
-            } => *control_flow = ControlFlow::Exit,
+            } => {
+                if window_id == main_window_id {
+                    *control_flow = ControlFlow::Exit;
+                } else {
+                    link_windows.remove(&window_id);
+                }
+            }
+
+            Event::LoopDestroyed => profile.close(),
+
+            Event::UserEvent(UserEvent::OpenLink(url)) => {
+                let text = last_clicked
+                    .take()
+                    .filter(|(clicked, _)| *clicked == url)
+                    .map(|(_, text)| text);
+
+                let (url, target, warnings) = match config.lock() {
+                    Ok(cfg) => {
+                        let cleaned = cfg.link_cleaning.clean(&url);
+                        if cleaned != url {
+                            eprintln!("[LINKS] Cleaned {} -> {}", url, cleaned);
+                        }
+                        let url = cleaned;
+
+                        let target = links::find_target(&cfg.link_rules, &url);
+                        let launches = !matches!(target, LinkTarget::Clipboard | LinkTarget::Block);
+                        let warnings = if launches && cfg.link_warnings {
+                            linkcheck::check(&url, text.as_deref(), &cfg.link_shorteners)
+                        } else {
+                            Vec::new()
+                        };
+                        (url, target, warnings)
+                    }
+                    Err(_) => {
+                        let shorteners = linkcheck::default_shorteners();
+                        let warnings = linkcheck::check(&url, text.as_deref(), &shorteners);
+                        (url, LinkTarget::System, warnings)
+                    }
+                };
+                eprintln!("[LINKS] {} -> {:?}", url, target);
+
+                if warnings.is_empty() {
+                    launch_link(
+                        &url,
+                        target,
+                        &config,
+                        &webview,
+                        event_loop_target,
+                        &mut link_windows,
+                    );
+                    return;
+                }
+
+                for warning in &warnings {
+                    eprintln!("[LINKS] Warning: {}", warning.describe());
+                }
+                let id = next_link_id;
+                next_link_id += 1;
+                ask_link_warning(&chooser, link_proxy.clone(), id, &url, &warnings);
+                pending_links.insert(id, (url, target));
+            }
+
+            Event::UserEvent(UserEvent::Ipc(IpcMessage::LinkClicked { url, text })) => {
+                last_clicked = Some((url, text));
+            }
+
+            Event::UserEvent(UserEvent::LinkDecided(decision)) => match decision {
+                LinkDecision::Open(id) => {
+                    if let Some((url, target)) = pending_links.remove(&id) {
+                        eprintln!("[LINKS] Opening flagged link on request: {}", url);
+                        launch_link(
+                            &url,
+                            target,
+                            &config,
+                            &webview,
+                            event_loop_target,
+                            &mut link_windows,
+                        );
+                    }
+                }
+                LinkDecision::Cancel(id) => {
+                    if let Some((url, _)) = pending_links.remove(&id) {
+                        eprintln!("[LINKS] Not opening flagged link: {}", url);
+                    }
+                }
+            },
+
+            Event::UserEvent(UserEvent::DownloadProcessed(report)) => {
+                eprintln!(
+                    "[DOWNLOAD] Processed: {} -> {:?} ({:?}, {:?})",
+                    report.url, report.path, report.after, report.action
+                );
+
+                let name = report
+                    .path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+
+                match report.verdict {
+                    Some(ScanVerdict::Infected(_)) => {
+                        let message = format!("Threat found in {}, moved to quarantine", name);
+                        show_toast(&webview, &message, "#ff6b6b");
+                        return;
+                    }
+                    Some(ScanVerdict::Failed(_)) => {
+                        let message = format!("Could not scan {}, saved without opening", name);
+                        show_toast(&webview, &message, "#ff9f43");
+                        return;
+                    }
+                    _ => {}
+                }
+
+                if report.after == AfterDownload::Nothing {
+                    return;
+                }
+
+                if report.duplicate_of.is_some() {
+                    show_toast(
+                        &webview,
+                        &format!("Already downloaded: {}", name),
+                        "#25D366",
+                    );
+                    return;
+                }
+
+                show_toast(&webview, &format!("Downloaded {}", name), "#25D366");
+            }
+
+            Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
+                next_retention = retention::next_run(retention::spawn(
+                    &config,
+                    history.clone(),
+                    retention_proxy.clone(),
+                ));
+                *control_flow = ControlFlow::WaitUntil(next_retention);
+            }
+
+            Event::UserEvent(UserEvent::DownloadRejected(message)) => {
+                show_toast(&webview, &message, "#ff6b6b");
+            }
+
+            Event::UserEvent(UserEvent::RetentionDone(report)) => {
+                if report.files.is_empty() {
+                    return;
+                }
+                let megabytes = report.bytes as f64 / (1024.0 * 1024.0);
+                let message = if report.dry_run {
+                    format!(
+                        "Retention dry run: would delete {} downloads ({:.1} MB)",
+                        report.files.len(),
+                        megabytes
+                    )
+                } else {
+                    format!(
+                        "Deleted {} old downloads ({:.1} MB)",
+                        report.files.len(),
+                        megabytes
+                    )
+                };
+                show_toast(&webview, &message, "#25D366");
+            }
+
+            Event::UserEvent(UserEvent::Ipc(IpcMessage::ShowHistory)) => {
+                show_history(&webview, &history);
+            }
+
+            Event::UserEvent(UserEvent::Ipc(IpcMessage::History(command))) => {
+                history::run_command(command, &history, &config, &chooser, &history_proxy);
+            }
+
+            Event::UserEvent(UserEvent::HistoryChanged) => {
+                show_history(&webview, &history);
+            }
+
+            Event::UserEvent(UserEvent::Ipc(IpcMessage::Download(command))) => {
+                #[cfg(target_os = "linux")]
+                download_tracker.run_command(command);
+
+                // Downloads aren't tracked here, the panel never shows
+                #[cfg(not(target_os = "linux"))]
+                let _ = command;
+            }
+
+            Event::UserEvent(UserEvent::PermissionAnswered(answer)) => {
+                #[cfg(target_os = "linux")]
+                permission_prompts.answer(answer);
+
+                // Requests are decided by the platform here, no prompt shows
+                #[cfg(not(target_os = "linux"))]
+                let _ = answer;
+            }
+
+            Event::UserEvent(UserEvent::CaptureChanged(state)) => {
+                window.set_title(&state.title("WhatsApp"));
+                let _ = webview.evaluate_script(&state.badge_script());
+            }
+
+            Event::UserEvent(UserEvent::Ipc(IpcMessage::StopCapture)) => {
+                #[cfg(target_os = "linux")]
+                {
+                    use wry::WebViewExtUnix;
+                    capture::stop_all(&webview.webview());
+                }
+            }
+
+            Event::UserEvent(UserEvent::ShowChooser(request)) => {
+                #[cfg(target_os = "linux")]
+                {
+                    use tao::platform::unix::WindowExtUnix;
+                    dialogs::show_native_dialog(window.gtk_window(), request);
+                }
+
+                // No native dialogs here; dropping the request answers "cancel"
+                #[cfg(not(target_os = "linux"))]
+                drop(request);
+            }
 
             _ => {}
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_only_overrides() {
+        assert_eq!(Config::default().overrides(), Some(serde_json::json!({})));
+
+        let cfg = Config {
+            link_warnings: false,
+            pdf_browser: Some(Browser::Firefox),
+            ..Config::default()
+        };
+        let overrides = cfg.overrides().unwrap();
+        assert_eq!(
+            overrides,
+            serde_json::json!({ "link_warnings": false, "pdf_browser": "Firefox" })
+        );
+
+        // Settings left out come back as the built-in defaults
+        let loaded: Config = serde_json::from_value(overrides).unwrap();
+        assert!(!loaded.link_warnings);
+        assert_eq!(loaded.pdf_browser, Some(Browser::Firefox));
+        assert_eq!(loaded.open_rules, rules::default_rules());
+    }
+
+    #[test]
+    fn changed_maps_are_saved_whole() {
+        let mut cfg = Config::default();
+        cfg.permissions.defaults.insert(
+            permissions::PermissionKind::Camera,
+            permissions::PermissionChoice::Allow,
+        );
+
+        let loaded: Config = serde_json::from_value(cfg.overrides().unwrap()).unwrap();
+        assert_eq!(loaded.permissions, cfg.permissions);
+    }
+}
@@ -0,0 +1,427 @@
+//! Permission policy for web content
+//!
+//! Every permission request (microphone, camera, location, notifications,
+//! ...) is decided by [`PermissionPolicy`]: a per-origin answer if one was
+//! remembered or configured, else the default for the permission type. An
+//! `Ask` decision shows a native chooser; its [`PermissionAnswer`] comes back
+//! to the event loop and can be remembered for the origin. The page never
+//! sees the prompt, so it can't answer it itself.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The kinds of permission a page can ask for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum PermissionKind {
+    Microphone,
+    Camera,
+    /// Sharing the screen or a window
+    ScreenShare,
+    Geolocation,
+    Notifications,
+    PointerLock,
+    /// Listing the names of cameras and microphones
+    DeviceInfo,
+    /// Playing DRM-protected media
+    MediaKeySystem,
+    /// Storage access for embedded third-party content
+    WebsiteData,
+    /// Anything not listed above
+    Other,
+}
+
+impl PermissionKind {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            PermissionKind::Microphone => "your microphone",
+            PermissionKind::Camera => "your camera",
+            PermissionKind::ScreenShare => "share your screen",
+            PermissionKind::Geolocation => "your location",
+            PermissionKind::Notifications => "show notifications",
+            PermissionKind::PointerLock => "lock the mouse pointer",
+            PermissionKind::DeviceInfo => "see your cameras and microphones",
+            PermissionKind::MediaKeySystem => "play protected media",
+            PermissionKind::WebsiteData => "use cookies in embedded content",
+            PermissionKind::Other => "an unknown permission",
+        }
+    }
+}
+
+/// How a permission request is answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PermissionChoice {
+    Allow,
+    Deny,
+    Ask,
+}
+
+/// Permission settings, with remembered answers per origin
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PermissionPolicy {
+    /// Choice per permission type; types not listed are asked
+    pub defaults: BTreeMap<PermissionKind, PermissionChoice>,
+    /// Per-origin choices, e.g. `https://web.whatsapp.com`; these win over
+    /// the defaults and include remembered prompt answers
+    pub origins: BTreeMap<String, BTreeMap<PermissionKind, PermissionChoice>>,
+}
+
+impl Default for PermissionPolicy {
+    fn default() -> Self {
+        let defaults = [
+            (PermissionKind::Microphone, PermissionChoice::Ask),
+            (PermissionKind::Camera, PermissionChoice::Ask),
+            (PermissionKind::ScreenShare, PermissionChoice::Ask),
+            (PermissionKind::Geolocation, PermissionChoice::Ask),
+            // The notification mute (Ctrl+Shift+M) relies on these
+            (PermissionKind::Notifications, PermissionChoice::Allow),
+            // Calls need the device list to offer a choice of devices
+            (PermissionKind::DeviceInfo, PermissionChoice::Allow),
+            (PermissionKind::PointerLock, PermissionChoice::Deny),
+            (PermissionKind::MediaKeySystem, PermissionChoice::Deny),
+            (PermissionKind::WebsiteData, PermissionChoice::Deny),
+            (PermissionKind::Other, PermissionChoice::Deny),
+        ];
+
+        Self {
+            defaults: defaults.into_iter().collect(),
+            origins: BTreeMap::new(),
+        }
+    }
+}
+
+impl PermissionPolicy {
+    pub fn choice_for(&self, origin: &str, kind: PermissionKind) -> PermissionChoice {
+        self.origins
+            .get(origin)
+            .and_then(|choices| choices.get(&kind))
+            .or_else(|| self.defaults.get(&kind))
+            .copied()
+            .unwrap_or(PermissionChoice::Ask)
+    }
+
+    /// Decide a request for several kinds at once (microphone and camera
+    /// for a video call): any denial denies, all allowed allows, else ask
+    pub fn decide(&self, origin: &str, kinds: &[PermissionKind]) -> PermissionChoice {
+        let choices: Vec<_> = kinds.iter().map(|&k| self.choice_for(origin, k)).collect();
+
+        if choices.contains(&PermissionChoice::Deny) {
+            PermissionChoice::Deny
+        } else if choices.iter().all(|&c| c == PermissionChoice::Allow) {
+            PermissionChoice::Allow
+        } else {
+            PermissionChoice::Ask
+        }
+    }
+
+    /// Store an answer for an origin
+    pub fn remember(&mut self, origin: &str, kinds: &[PermissionKind], allow: bool) {
+        let choice = if allow {
+            PermissionChoice::Allow
+        } else {
+            PermissionChoice::Deny
+        };
+        let choices = self.origins.entry(origin.to_string()).or_default();
+        for &kind in kinds {
+            choices.insert(kind, choice);
+        }
+    }
+}
+
+/// The prompt's answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PermissionAnswer {
+    pub id: u64,
+    pub allow: bool,
+    pub remember: bool,
+}
+
+/// `scheme://host[:port]` of a page, the key for remembered answers
+pub fn origin_of(uri: &str) -> String {
+    url::Url::parse(uri)
+        .map(|url| url.origin().ascii_serialization())
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "linux")]
+pub use prompts::PermissionPrompts;
+
+#[cfg(target_os = "linux")]
+mod prompts {
+    use super::{origin_of, PermissionAnswer, PermissionChoice, PermissionKind};
+    use crate::dialogs::Chooser;
+    use crate::{Config, UserEvent};
+    use gtk::prelude::*;
+    use std::cell::{Cell, RefCell};
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
+    use tao::event_loop::EventLoopProxy;
+    use webkit2gtk::{
+        PermissionRequestExt, UserMediaPermissionRequestExt, WebViewExt,
+        WebsiteDataAccessPermissionRequestExt,
+    };
+
+    struct Pending {
+        request: webkit2gtk::PermissionRequest,
+        origin: String,
+        kinds: Vec<PermissionKind>,
+    }
+
+    /// Permission requests waiting for an answer; lives on the main thread
+    pub struct PermissionPrompts {
+        webview: webkit2gtk::WebView,
+        config: Arc<Mutex<Config>>,
+        chooser: Chooser,
+        proxy: EventLoopProxy<UserEvent>,
+        pending: RefCell<BTreeMap<u64, Pending>>,
+        next_id: Cell<u64>,
+    }
+
+    impl PermissionPrompts {
+        pub fn new(
+            webview: webkit2gtk::WebView,
+            config: Arc<Mutex<Config>>,
+            chooser: Chooser,
+            proxy: EventLoopProxy<UserEvent>,
+        ) -> Rc<Self> {
+            Rc::new(Self {
+                webview,
+                config,
+                chooser,
+                proxy,
+                pending: RefCell::default(),
+                next_id: Cell::new(0),
+            })
+        }
+
+        /// Decide a request from WebKit, prompting if the policy says so
+        pub fn handle(&self, request: &webkit2gtk::PermissionRequest) {
+            let kinds = classify(request);
+            let origin = self.requesting_origin(request);
+
+            let choice = self
+                .config
+                .lock()
+                .map(|cfg| cfg.permissions.decide(&origin, &kinds))
+                .unwrap_or(PermissionChoice::Deny);
+            eprintln!("[PERMISSION] {} asks for {:?}: {:?}", origin, kinds, choice);
+
+            match choice {
+                PermissionChoice::Allow => request.allow(),
+                PermissionChoice::Deny => request.deny(),
+                PermissionChoice::Ask => {
+                    let id = self.next_id.get();
+                    self.next_id.set(id + 1);
+                    self.ask(id, &origin, &kinds);
+                    self.pending.borrow_mut().insert(
+                        id,
+                        Pending {
+                            request: request.clone(),
+                            origin,
+                            kinds,
+                        },
+                    );
+                }
+            }
+        }
+
+        /// Apply the user's answer from the chooser
+        pub fn answer(&self, answer: PermissionAnswer) {
+            let Some(pending) = self.pending.borrow_mut().remove(&answer.id) else {
+                return;
+            };
+            eprintln!(
+                "[PERMISSION] {} {:?}: {}",
+                pending.origin,
+                pending.kinds,
+                if answer.allow { "allowed" } else { "denied" }
+            );
+
+            if answer.allow {
+                pending.request.allow();
+            } else {
+                pending.request.deny();
+            }
+
+            if answer.remember && !pending.origin.is_empty() {
+                crate::update_config(&self.config, |cfg| {
+                    cfg.permissions
+                        .remember(&pending.origin, &pending.kinds, answer.allow)
+                });
+            }
+        }
+
+        /// Who is asking. Storage access requests name the embedded site;
+        /// for the others WebKit doesn't expose the requesting frame, and a
+        /// cross-origin frame only gets to ask if the top-level page
+        /// delegated the feature to it (`<iframe allow="camera">`), so the
+        /// request is the top-level page's.
+        fn requesting_origin(&self, request: &webkit2gtk::PermissionRequest) -> String {
+            if let Some(access) =
+                request.dynamic_cast_ref::<webkit2gtk::WebsiteDataAccessPermissionRequest>()
+            {
+                if let Some(domain) = access.requesting_domain() {
+                    return format!("https://{}", domain);
+                }
+            }
+
+            self.webview
+                .uri()
+                .map(|uri| origin_of(&uri))
+                .unwrap_or_default()
+        }
+
+        /// Show the chooser from a worker thread, which blocks until it is
+        /// answered; the answer comes back as `UserEvent::PermissionAnswered`
+        fn ask(&self, id: u64, origin: &str, kinds: &[PermissionKind]) {
+            let wants: Vec<_> = kinds.iter().map(PermissionKind::describe).collect();
+            let text = format!(
+                "{} wants to use {}.",
+                if origin.is_empty() {
+                    "This page"
+                } else {
+                    origin
+                },
+                list(&wants)
+            );
+            let chooser = self.chooser.clone();
+            let proxy = self.proxy.clone();
+
+            std::thread::spawn(move || {
+                // Deny comes first, so it is what a careless Enter picks
+                let choice =
+                    chooser.choose_with("Permission request", &text, &["Deny", "Allow"], "OK");
+                let answer = PermissionAnswer {
+                    id,
+                    allow: choice.is_some_and(|c| c.index == 1),
+                    remember: choice.is_some_and(|c| c.remember),
+                };
+                let _ = proxy.send_event(UserEvent::PermissionAnswered(answer));
+            });
+        }
+    }
+
+    /// "a", "a and b", "a, b and c"
+    fn list(items: &[&str]) -> String {
+        match items {
+            [rest @ .., last] if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+            _ => items.concat(),
+        }
+    }
+
+    /// What a WebKit permission request is for
+    fn classify(request: &webkit2gtk::PermissionRequest) -> Vec<PermissionKind> {
+        if let Some(media) = request.dynamic_cast_ref::<webkit2gtk::UserMediaPermissionRequest>() {
+            let mut kinds = Vec::new();
+            if is_for_display(media) {
+                kinds.push(PermissionKind::ScreenShare);
+            }
+            if media.is_for_audio_device() {
+                kinds.push(PermissionKind::Microphone);
+            }
+            if media.is_for_video_device() {
+                kinds.push(PermissionKind::Camera);
+            }
+            if kinds.is_empty() {
+                kinds.push(PermissionKind::Other);
+            }
+            return kinds;
+        }
+
+        let kind = if request.is::<webkit2gtk::GeolocationPermissionRequest>() {
+            PermissionKind::Geolocation
+        } else if request.is::<webkit2gtk::NotificationPermissionRequest>() {
+            PermissionKind::Notifications
+        } else if request.is::<webkit2gtk::PointerLockPermissionRequest>() {
+            PermissionKind::PointerLock
+        } else if request.is::<webkit2gtk::DeviceInfoPermissionRequest>() {
+            PermissionKind::DeviceInfo
+        } else if request.is::<webkit2gtk::MediaKeySystemPermissionRequest>() {
+            PermissionKind::MediaKeySystem
+        } else if request.is::<webkit2gtk::WebsiteDataAccessPermissionRequest>() {
+            PermissionKind::WebsiteData
+        } else {
+            PermissionKind::Other
+        };
+        vec![kind]
+    }
+
+    /// Whether a media request is for the screen (`getDisplayMedia`); the
+    /// bindings don't wrap this one
+    fn is_for_display(media: &webkit2gtk::UserMediaPermissionRequest) -> bool {
+        use gtk::glib::translate::ToGlibPtr;
+        unsafe {
+            webkit2gtk::ffi::webkit_user_media_permission_is_for_display_device(
+                media.to_glib_none().0,
+            ) != gtk::glib::ffi::GFALSE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PermissionChoice::*;
+    use PermissionKind::*;
+
+    const WHATSAPP: &str = "https://web.whatsapp.com";
+
+    #[test]
+    fn choice_falls_back_to_defaults_then_ask() {
+        let mut policy = PermissionPolicy::default();
+        policy.defaults.remove(&Geolocation);
+        policy
+            .origins
+            .entry(WHATSAPP.to_string())
+            .or_default()
+            .insert(Camera, Allow);
+
+        assert_eq!(policy.choice_for(WHATSAPP, Camera), Allow);
+        assert_eq!(policy.choice_for("https://example.com", Camera), Ask);
+        assert_eq!(policy.choice_for(WHATSAPP, Notifications), Allow);
+        assert_eq!(policy.choice_for(WHATSAPP, Other), Deny);
+        // Not in the defaults
+        assert_eq!(policy.choice_for(WHATSAPP, Geolocation), Ask);
+    }
+
+    #[test]
+    fn screen_sharing_is_asked_by_default() {
+        let policy = PermissionPolicy::default();
+        assert_eq!(policy.decide(WHATSAPP, &[ScreenShare]), Ask);
+        assert_eq!(policy.decide(WHATSAPP, &[ScreenShare, Microphone]), Ask);
+    }
+
+    #[test]
+    fn decide_combines_kinds() {
+        let mut policy = PermissionPolicy::default();
+        policy.defaults.insert(Microphone, Allow);
+
+        assert_eq!(policy.decide(WHATSAPP, &[Microphone]), Allow);
+        // Allowed and asked
+        assert_eq!(policy.decide(WHATSAPP, &[Microphone, Camera]), Ask);
+        // Any denial wins
+        assert_eq!(policy.decide(WHATSAPP, &[Microphone, Camera, Other]), Deny);
+
+        policy.defaults.insert(Camera, Allow);
+        assert_eq!(policy.decide(WHATSAPP, &[Microphone, Camera]), Allow);
+    }
+
+    #[test]
+    fn remember_overrides_defaults_for_one_origin() {
+        let mut policy = PermissionPolicy::default();
+
+        policy.remember(WHATSAPP, &[Microphone, Camera], true);
+        assert_eq!(policy.decide(WHATSAPP, &[Microphone, Camera]), Allow);
+        assert_eq!(policy.decide("https://example.com", &[Microphone]), Ask);
+
+        policy.remember(WHATSAPP, &[Camera], false);
+        assert_eq!(policy.choice_for(WHATSAPP, Microphone), Allow);
+        assert_eq!(policy.decide(WHATSAPP, &[Microphone, Camera]), Deny);
+
+        // Even over a denying default
+        policy.remember(WHATSAPP, &[PointerLock], true);
+        assert_eq!(policy.choice_for(WHATSAPP, PointerLock), Allow);
+        assert_eq!(policy.choice_for("https://example.com", PointerLock), Deny);
+    }
+}
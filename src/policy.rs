@@ -0,0 +1,60 @@
+//! System-wide policy set by an administrator
+//!
+//! `/etc/whatsapp-desktop/policy.json` holds settings users can't override
+//! from their own config or the command line. A policy file that exists but
+//! can't be read or parsed is treated as the strictest policy, so breaking
+//! it never loosens anything.
+
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+
+/// Administrator settings; everything is allowed when there is no file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    /// Developer tools stay off, whatever `--devtools` or the config say
+    pub disable_devtools: bool,
+}
+
+impl Policy {
+    pub fn path() -> PathBuf {
+        PathBuf::from("/etc/whatsapp-desktop/policy.json")
+    }
+
+    pub fn load() -> Self {
+        let path = Self::path();
+        if !path.exists() {
+            return Self::default();
+        }
+
+        let parsed = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(policy) => {
+                eprintln!("[POLICY] Loaded {:?}", path);
+                policy
+            }
+            Err(e) => {
+                eprintln!("[POLICY] Can't use {:?} ({}), locking everything", path, e);
+                Self::strictest()
+            }
+        }
+    }
+
+    fn strictest() -> Self {
+        Self {
+            disable_devtools: true,
+        }
+    }
+
+    /// Whether developer tools may be turned on, given that they were asked
+    /// for by `--devtools` or the config
+    pub fn devtools(&self, requested: bool) -> bool {
+        if requested && self.disable_devtools {
+            eprintln!("[POLICY] Developer tools are disabled by the system policy");
+        }
+        requested && !self.disable_devtools
+    }
+}
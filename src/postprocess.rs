@@ -0,0 +1,291 @@
+//! Background post-processing of finished downloads
+//!
+//! Sniffing, renaming and opening a file can block for a long time (the
+//! "open with" dialogs wait for the user), so none of it runs inside the GTK
+//! download callbacks. Finished downloads are queued here, handled by a small
+//! pool of worker threads, and the outcome is sent back to the event loop as a
+//! [`UserEvent::DownloadProcessed`].
+
+use crate::dedup::{self, DedupMode};
+use crate::desktop::{self, NotificationAction};
+use crate::dialogs::Chooser;
+use crate::downloads;
+use crate::history::History;
+use crate::rules::OpenAction;
+use crate::scan::ScanVerdict;
+use crate::sniff::Category;
+use crate::{fix_file_extension, handle_file_open, Config, UserEvent};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tao::event_loop::EventLoopProxy;
+
+/// Number of downloads that can be processed at the same time
+const WORKERS: usize = 4;
+
+/// What happens once a download finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AfterDownload {
+    /// Open the file according to the open rules
+    #[default]
+    Open,
+    /// Show the file in the file manager
+    Reveal,
+    /// Desktop notification with Open / Show in Folder buttons
+    Notify,
+    /// Just keep the file
+    Nothing,
+}
+
+/// A finished download waiting to be processed
+#[derive(Debug, Clone)]
+pub struct DownloadJob {
+    pub url: String,
+    pub path: PathBuf,
+}
+
+/// What happened to a processed download
+#[derive(Debug, Clone)]
+pub struct DownloadReport {
+    pub url: String,
+    /// Final location, after the extension was fixed
+    pub path: PathBuf,
+    pub after: AfterDownload,
+    /// Open rule that ran, if the file was opened right away
+    pub action: Option<OpenAction>,
+    /// Malware-scan result, if a scanner is configured
+    pub verdict: Option<ScanVerdict>,
+    /// Content hash of the file
+    pub hash: Option<String>,
+    /// History id of an earlier download with the same contents
+    pub duplicate_of: Option<u64>,
+}
+
+impl DownloadReport {
+    /// A download that was kept but not acted on
+    fn kept(url: String, path: PathBuf, verdict: Option<ScanVerdict>) -> Self {
+        Self {
+            url,
+            path,
+            after: AfterDownload::Nothing,
+            action: None,
+            verdict,
+            hash: None,
+            duplicate_of: None,
+        }
+    }
+}
+
+/// Handle for queueing finished downloads
+#[derive(Clone)]
+pub struct PostProcessor {
+    jobs: Sender<DownloadJob>,
+}
+
+impl PostProcessor {
+    /// Start the worker threads
+    pub fn spawn(
+        config: Arc<Mutex<Config>>,
+        history: Arc<Mutex<History>>,
+        chooser: Chooser,
+        proxy: EventLoopProxy<UserEvent>,
+    ) -> Self {
+        let (jobs, queue) = mpsc::channel::<DownloadJob>();
+        let queue = Arc::new(Mutex::new(queue));
+
+        for id in 0..WORKERS {
+            let queue = queue.clone();
+            let config = config.clone();
+            let history = history.clone();
+            let chooser = chooser.clone();
+            let proxy = proxy.clone();
+
+            let spawned = thread::Builder::new()
+                .name(format!("download-worker-{}", id))
+                .spawn(move || worker(queue, config, history, chooser, proxy));
+
+            if let Err(e) = spawned {
+                eprintln!("[DOWNLOAD] Failed to start worker {}: {}", id, e);
+            }
+        }
+
+        Self { jobs }
+    }
+
+    /// Queue a finished download; returns immediately
+    pub fn submit(&self, job: DownloadJob) {
+        if let Err(e) = self.jobs.send(job) {
+            eprintln!("[DOWNLOAD] Post-processing queue closed: {:?}", e.0.path);
+        }
+    }
+}
+
+fn worker(
+    queue: Arc<Mutex<Receiver<DownloadJob>>>,
+    config: Arc<Mutex<Config>>,
+    history: Arc<Mutex<History>>,
+    chooser: Chooser,
+    proxy: EventLoopProxy<UserEvent>,
+) {
+    loop {
+        // Only hold the lock while waiting, not while processing
+        let job = match queue.lock() {
+            Ok(queue) => queue.recv(),
+            Err(_) => return,
+        };
+        let Ok(job) = job else {
+            return;
+        };
+
+        let report = process(job, &config, &history, &chooser);
+        if let Ok(mut history) = history.lock() {
+            history.record(&report);
+            history.save();
+        }
+
+        if proxy
+            .send_event(UserEvent::DownloadProcessed(report))
+            .is_err()
+        {
+            // Event loop is gone, the app is shutting down
+            return;
+        }
+    }
+}
+
+/// Sniff, rename, scan, deduplicate and sort the file, then open, reveal or
+/// announce it
+fn process(
+    job: DownloadJob,
+    config: &Arc<Mutex<Config>>,
+    history: &Mutex<History>,
+    chooser: &Chooser,
+) -> DownloadReport {
+    let path = fix_file_extension(&job.path);
+    let category = Category::of(&path);
+    let (after, subfolder, scanner, dedup_mode) = config
+        .lock()
+        .map(|cfg| {
+            (
+                cfg.after_download_for(category),
+                cfg.download_subfolder.clone(),
+                cfg.scanner(),
+                cfg.dedup,
+            )
+        })
+        .unwrap_or_default();
+
+    let verdict = scanner.as_ref().map(|scanner| scanner.scan(&path));
+    let path = match (&verdict, &scanner) {
+        (Some(ScanVerdict::Infected(output)), Some(scanner)) => {
+            eprintln!("[SCAN] Threat found in {:?}: {}", path, output);
+            let path = match scanner.quarantine(&path) {
+                Ok(dest) => {
+                    eprintln!("[SCAN] Quarantined to {:?}", dest);
+                    dest
+                }
+                Err(e) => {
+                    eprintln!("[SCAN] Failed to quarantine {:?}: {}", path, e);
+                    path
+                }
+            };
+            return DownloadReport::kept(job.url, path, verdict);
+        }
+        // Keep files that couldn't be scanned, but don't open them
+        (Some(ScanVerdict::Failed(reason)), _) => {
+            eprintln!("[SCAN] Could not scan {:?}: {}", path, reason);
+            return DownloadReport::kept(job.url, path, verdict);
+        }
+        _ => path,
+    };
+
+    let hash = dedup::hash_file(&path)
+        .inspect_err(|e| eprintln!("[DEDUP] Failed to hash {:?}: {}", path, e))
+        .ok();
+    let (path, duplicate_of) = match &hash {
+        Some(hash) => dedup::deduplicate(&path, hash, dedup_mode, history),
+        None => (path, None),
+    };
+
+    // A referenced original already sits where it was sorted to
+    let referenced = duplicate_of.is_some() && dedup_mode == DedupMode::Reference;
+    let path = match subfolder {
+        Some(template) if !referenced => move_to_subfolder(&path, &template, category),
+        _ => path,
+    };
+
+    let action = match after {
+        AfterDownload::Open => Some(handle_file_open(&path, config, chooser)),
+        AfterDownload::Reveal => {
+            desktop::reveal_in_folder(&path);
+            None
+        }
+        AfterDownload::Notify => {
+            notify_in_background(path.clone(), config.clone(), chooser.clone());
+            None
+        }
+        AfterDownload::Nothing => None,
+    };
+
+    DownloadReport {
+        url: job.url,
+        path,
+        after,
+        action,
+        verdict,
+        hash,
+        duplicate_of,
+    }
+}
+
+/// Move a file from the download root into its templated subfolder, returning
+/// the new location (or the old one if the move failed)
+fn move_to_subfolder(path: &Path, template: &str, category: Category) -> PathBuf {
+    let (Some(root), Some(filename)) = (path.parent(), path.file_name()) else {
+        return path.to_path_buf();
+    };
+
+    let subfolder = downloads::expand_subfolder(template, category, downloads::today());
+    if subfolder.as_os_str().is_empty() {
+        return path.to_path_buf();
+    }
+
+    let dir = root.join(subfolder);
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("[DOWNLOAD] Cannot create {:?}: {}", dir, e);
+        return path.to_path_buf();
+    }
+
+    let dest = downloads::unique_path(&dir, &filename.to_string_lossy(), |_| false);
+    match fs::rename(path, &dest) {
+        Ok(()) => {
+            eprintln!("[DOWNLOAD] Sorted into {:?}", dest);
+            dest
+        }
+        Err(e) => {
+            eprintln!("[DOWNLOAD] Failed to move {:?}: {}", path, e);
+            path.to_path_buf()
+        }
+    }
+}
+
+/// Wait for the notification on a thread of its own so a worker isn't tied
+/// up until the user reacts
+fn notify_in_background(path: PathBuf, config: Arc<Mutex<Config>>, chooser: Chooser) {
+    let spawned = thread::Builder::new()
+        .name("download-notification".to_string())
+        .spawn(move || match desktop::notify_download(&path) {
+            Some(NotificationAction::Open) => {
+                handle_file_open(&path, &config, &chooser);
+            }
+            Some(NotificationAction::Show) => desktop::reveal_in_folder(&path),
+            None => {}
+        });
+
+    if let Err(e) = spawned {
+        eprintln!("[NOTIFY] Failed to start notification thread: {}", e);
+    }
+}
@@ -0,0 +1,592 @@
+//! Location of the WebKit profile, optionally encrypted at rest
+//!
+//! The profile is the `webdata` directory plus the SQLite cookie store. By
+//! default both sit in `~/.local/share/whatsapp-desktop/`. With
+//! `Config::profile_encryption` set they are kept in a gocryptfs file set in
+//! `profile.crypt/` instead, mounted at startup below `$XDG_RUNTIME_DIR` and
+//! unmounted on exit. The key is either a random one stored in the Secret
+//! Service (gnome-keyring, KWallet, ...) through `secret-tool`, or a
+//! passphrase asked for at startup. If the profile can't be unlocked the app
+//! doesn't start, rather than falling back to unencrypted storage.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How the profile is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ProfileEncryption {
+    /// Plain files in the data directory
+    #[default]
+    Off,
+    /// Encrypted with a random key kept in the Secret Service
+    Keyring,
+    /// Encrypted with a passphrase asked for at every start
+    Passphrase,
+}
+
+/// Where WebKit keeps its data for this run
+#[derive(Debug)]
+pub struct Profile {
+    pub webdata: PathBuf,
+    pub cookies: PathBuf,
+    /// Mountpoint of the decrypted view, unmounted by [`Profile::close`] or
+    /// when the profile is dropped
+    mount: Option<PathBuf>,
+}
+
+fn base_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("whatsapp-desktop")
+}
+
+impl Profile {
+    /// Find, and if needed unlock, the profile
+    pub fn open(encryption: ProfileEncryption) -> Result<Self, String> {
+        let base = base_dir();
+        remove_plaintext_cookies(&base);
+
+        if encryption == ProfileEncryption::Off {
+            return Ok(Self::in_dir(base, false));
+        }
+
+        #[cfg(target_os = "linux")]
+        return crypt::open(&base, encryption).map(|mount| Self::in_dir(mount, true));
+
+        #[cfg(not(target_os = "linux"))]
+        Err(format!(
+            "profile encryption ({:?}) is only supported on Linux",
+            encryption
+        ))
+    }
+
+    fn in_dir(dir: PathBuf, mounted: bool) -> Self {
+        let webdata = dir.join("webdata");
+        if let Err(e) = fs::create_dir_all(&webdata) {
+            eprintln!("[WARN] Failed to create data directory: {}", e);
+        }
+        Self {
+            webdata,
+            cookies: dir.join("cookies.sqlite"),
+            mount: Some(dir).filter(|_| mounted),
+        }
+    }
+
+    /// Unmount the decrypted view. Lazily, since WebKit may still have files
+    /// open while the app exits.
+    pub fn close(&mut self) {
+        if let Some(mount) = self.mount.take() {
+            #[cfg(target_os = "linux")]
+            crypt::unmount(&mount);
+
+            // Nothing is ever mounted here
+            #[cfg(not(target_os = "linux"))]
+            let _ = mount;
+        }
+    }
+}
+
+/// Also unmounts when `main` returns early with an error
+impl Drop for Profile {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Cookies used to be written as plain text, first by wry to
+/// `webdata/cookies` and then to `cookies.txt`; they now live in SQLite.
+/// WhatsApp keeps its login in IndexedDB, so dropping them doesn't log out.
+fn remove_plaintext_cookies(base: &Path) {
+    for path in [
+        base.join("cookies.txt"),
+        base.join("webdata").join("cookies"),
+    ] {
+        if path.is_file() {
+            match fs::remove_file(&path) {
+                Ok(()) => eprintln!("[PROFILE] Removed plaintext cookies {:?}", path),
+                Err(e) => eprintln!("[PROFILE] Can't remove {:?}: {}", path, e),
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod crypt {
+    use super::ProfileEncryption;
+    use crate::dialogs;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use std::process::{Command, Stdio};
+
+    /// gocryptfs' exit code for a wrong password
+    const WRONG_PASSWORD: i32 = 12;
+    /// Passphrase attempts before giving up
+    const ATTEMPTS: usize = 3;
+    /// Client used to reach the Secret Service
+    const SECRET_TOOL: &str = "secret-tool";
+    /// Secret Service attributes the key is stored under
+    const KEY_ATTRIBUTES: [&str; 4] = ["application", "whatsapp-desktop", "type", "profile-key"];
+
+    /// Unlock and mount the encrypted profile; returns the mountpoint
+    pub fn open(base: &Path, encryption: ProfileEncryption) -> Result<PathBuf, String> {
+        let cipher_dir = base.join("profile.crypt");
+        let mount = mountpoint();
+
+        if is_mounted(&mount) {
+            eprintln!("[PROFILE] Encrypted profile already mounted at {:?}", mount);
+            return Ok(mount);
+        }
+
+        fs::create_dir_all(&mount)
+            .map_err(|e| format!("can't create mountpoint {:?}: {}", mount, e))?;
+
+        let fresh = !cipher_dir.join("gocryptfs.conf").exists();
+        if fresh {
+            eprintln!("[PROFILE] Creating encrypted profile in {:?}", cipher_dir);
+            fs::create_dir_all(&cipher_dir)
+                .map_err(|e| format!("can't create {:?}: {}", cipher_dir, e))?;
+        }
+
+        match encryption {
+            ProfileEncryption::Keyring => {
+                let key = keyring_key(SECRET_TOOL, fresh)?;
+                if fresh {
+                    init(&cipher_dir, &key)?;
+                }
+                if !mount_with(&cipher_dir, &mount, &key)? {
+                    return Err("the key in the keyring doesn't open the profile".into());
+                }
+            }
+            ProfileEncryption::Passphrase => {
+                unlock_with_passphrase(&cipher_dir, &mount, fresh)?;
+            }
+            ProfileEncryption::Off => unreachable!("unencrypted profiles aren't mounted"),
+        }
+        eprintln!("[PROFILE] Encrypted profile mounted at {:?}", mount);
+
+        if fresh {
+            migrate(base, &mount);
+        }
+        Ok(mount)
+    }
+
+    /// Private to the user and, on most systems, on a tmpfs
+    fn mountpoint() -> PathBuf {
+        let dir = dirs::runtime_dir()
+            .unwrap_or_else(super::base_dir)
+            .join("whatsapp-desktop");
+        let _ = fs::create_dir_all(&dir);
+        let _ = fs::set_permissions(&dir, fs::Permissions::from_mode(0o700));
+        dir.join("profile")
+    }
+
+    fn is_mounted(mount: &Path) -> bool {
+        let Ok(mounts) = fs::read_to_string("/proc/self/mounts") else {
+            return false;
+        };
+        mounts.lines().any(|line| {
+            line.split(' ')
+                .nth(1)
+                .is_some_and(|point| Path::new(&point.replace("\\040", " ")) == mount)
+        })
+    }
+
+    fn unlock_with_passphrase(cipher_dir: &Path, mount: &Path, fresh: bool) -> Result<(), String> {
+        if fresh {
+            let passphrase = dialogs::ask_passphrase(
+                "Encrypt WhatsApp profile",
+                "Choose a passphrase for your WhatsApp session data.\nIt will be asked for every time the app starts.",
+                true,
+            )
+            .ok_or("no passphrase was entered")?;
+            init(cipher_dir, &passphrase)?;
+            if !mount_with(cipher_dir, mount, &passphrase)? {
+                return Err("the new profile didn't accept its passphrase".into());
+            }
+            return Ok(());
+        }
+
+        let mut text = "Enter the passphrase for your WhatsApp session data.";
+        for _ in 0..ATTEMPTS {
+            let passphrase = dialogs::ask_passphrase("Unlock WhatsApp profile", text, false)
+                .ok_or("no passphrase was entered")?;
+            if mount_with(cipher_dir, mount, &passphrase)? {
+                return Ok(());
+            }
+            eprintln!("[PROFILE] Wrong passphrase");
+            text = "Wrong passphrase, try again.";
+        }
+        Err("wrong passphrase".into())
+    }
+
+    /// The profile key from the Secret Service; a new one is generated and
+    /// stored for a new profile
+    fn keyring_key(secret_tool: &str, fresh: bool) -> Result<String, String> {
+        if let Some(key) = lookup_key(secret_tool)? {
+            return Ok(key);
+        }
+        if !fresh {
+            return Err(
+                "the profile key is missing from the keyring, the profile can't be opened".into(),
+            );
+        }
+
+        let mut bytes = [0u8; 32];
+        fs::File::open("/dev/urandom")
+            .and_then(|mut random| random.read_exact(&mut bytes))
+            .map_err(|e| format!("can't generate a key: {}", e))?;
+        use base64::Engine;
+        let key = base64::engine::general_purpose::STANDARD.encode(bytes);
+
+        run_secret_tool(
+            secret_tool,
+            &["store", "--label=WhatsApp Desktop profile key"],
+            Some(&key),
+        )?;
+        // Only encrypt with a key the keyring really kept
+        match lookup_key(secret_tool)? {
+            Some(stored) if stored == key => {
+                eprintln!("[PROFILE] Stored new profile key in the keyring");
+                Ok(key)
+            }
+            _ => Err("the keyring didn't keep the new profile key".into()),
+        }
+    }
+
+    fn lookup_key(secret_tool: &str) -> Result<Option<String>, String> {
+        let output = run_secret_tool(secret_tool, &["lookup"], None)?;
+        Ok(Some(output).filter(|key| !key.is_empty()))
+    }
+
+    /// Run `secret-tool` with the key's attributes appended. A failing
+    /// `lookup` just means there is no key; anything else is an error.
+    fn run_secret_tool(
+        program: &str,
+        args: &[&str],
+        secret: Option<&str>,
+    ) -> Result<String, String> {
+        let mut child = Command::new(program)
+            .args(args)
+            .args(KEY_ATTRIBUTES)
+            .stdin(if secret.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| format!("can't run secret-tool (install libsecret-tools): {}", e))?;
+
+        // Read until EOF, so no trailing newline ends up in the secret
+        if let (Some(mut stdin), Some(secret)) = (child.stdin.take(), secret) {
+            let _ = stdin.write_all(secret.as_bytes());
+        }
+
+        let output = child
+            .wait_with_output()
+            .map_err(|e| format!("secret-tool failed: {}", e))?;
+        if !output.status.success() && args.first() != Some(&"lookup") {
+            return Err(format!(
+                "secret-tool {} failed ({})",
+                args[0], output.status
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    fn init(cipher_dir: &Path, password: &str) -> Result<(), String> {
+        let status = gocryptfs(&["-init".as_ref(), cipher_dir.as_os_str()], password)?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("gocryptfs -init failed ({})", status))
+        }
+    }
+
+    /// Mount the profile; `Ok(false)` if the password is wrong
+    fn mount_with(cipher_dir: &Path, mount: &Path, password: &str) -> Result<bool, String> {
+        let status = gocryptfs(&[cipher_dir.as_os_str(), mount.as_os_str()], password)?;
+        match status.code() {
+            Some(0) => Ok(true),
+            Some(WRONG_PASSWORD) => Ok(false),
+            _ => Err(format!("gocryptfs couldn't mount the profile ({})", status)),
+        }
+    }
+
+    /// Run gocryptfs with the password on stdin. It daemonizes once the
+    /// mount is up, so waiting for it is enough.
+    fn gocryptfs(
+        args: &[&std::ffi::OsStr],
+        password: &str,
+    ) -> Result<std::process::ExitStatus, String> {
+        let mut child = Command::new("gocryptfs")
+            .arg("-q")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| format!("can't run gocryptfs (is it installed?): {}", e))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = writeln!(stdin, "{}", password);
+        }
+        child.wait().map_err(|e| format!("gocryptfs failed: {}", e))
+    }
+
+    pub fn unmount(mount: &Path) {
+        for program in ["fusermount3", "fusermount"] {
+            let status = Command::new(program).args(["-u", "-z"]).arg(mount).status();
+            match status {
+                Ok(status) if status.success() => {
+                    eprintln!("[PROFILE] Unmounted {:?}", mount);
+                    return;
+                }
+                Ok(status) => {
+                    eprintln!("[PROFILE] {} -u {:?} failed ({})", program, mount, status);
+                    return;
+                }
+                // Not installed, try the next one
+                Err(_) => {}
+            }
+        }
+        eprintln!(
+            "[PROFILE] Neither fusermount3 nor fusermount could unmount {:?}",
+            mount
+        );
+    }
+
+    /// What an unencrypted profile consists of; SQLite keeps recent writes
+    /// in the journal files next to the cookie store
+    const PLAIN_PROFILE: [&str; 5] = [
+        "webdata",
+        "cookies.sqlite",
+        "cookies.sqlite-wal",
+        "cookies.sqlite-shm",
+        "cookies.sqlite-journal",
+    ];
+
+    /// Move an existing unencrypted profile into the new encrypted one
+    fn migrate(base: &Path, mount: &Path) {
+        for name in PLAIN_PROFILE {
+            let plain = base.join(name);
+            let copied = if plain.is_dir() {
+                copy_dir(&plain, &mount.join(name))
+            } else if plain.is_file() {
+                fs::copy(&plain, mount.join(name)).map(|_| ())
+            } else {
+                continue;
+            };
+
+            eprintln!("[PROFILE] Moving {:?} into the encrypted profile", plain);
+            match copied {
+                Ok(()) => {
+                    if let Err(e) = remove_securely(&plain) {
+                        eprintln!("[PROFILE] Can't remove unencrypted {:?}: {}", plain, e);
+                    }
+                }
+                Err(e) => eprintln!(
+                    "[PROFILE] Can't copy {:?}, starting without it: {}",
+                    plain, e
+                ),
+            }
+        }
+    }
+
+    /// Overwrite files with zeros before removing them, so the plaintext
+    /// isn't left in free blocks. Copy-on-write filesystems and SSDs may
+    /// still keep the old blocks; overwriting in place is all an app can do.
+    fn remove_securely(path: &Path) -> std::io::Result<()> {
+        let file_type = fs::symlink_metadata(path)?.file_type();
+        if file_type.is_dir() {
+            for entry in fs::read_dir(path)? {
+                remove_securely(&entry?.path())?;
+            }
+            return fs::remove_dir(path);
+        }
+
+        if file_type.is_file() {
+            let mut file = fs::OpenOptions::new().write(true).open(path)?;
+            let zeros = [0u8; 64 * 1024];
+            let mut left = file.metadata()?.len();
+            while left > 0 {
+                let chunk = left.min(zeros.len() as u64) as usize;
+                file.write_all(&zeros[..chunk])?;
+                left -= chunk as u64;
+            }
+            file.sync_all()?;
+        }
+        fs::remove_file(path)
+    }
+
+    fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            let target = to.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                copy_dir(&entry.path(), &target)?;
+            } else if file_type.is_file() {
+                fs::copy(entry.path(), &target)?;
+            }
+        }
+        Ok(())
+    }
+
+    // The Secret Service is only reached through `secret-tool`, so the tests
+    // stand in for that client. A mock service on a private bus
+    // (`dbus-run-session`) would have to implement the Secret Service
+    // protocol's sessions, collections and prompts before `secret-tool`
+    // talks to it, and `secret-tool` itself isn't installed on build
+    // machines; the stub covers everything this module decides on its own.
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// A fresh directory for one test
+        fn temp_dir(name: &str) -> PathBuf {
+            let dir = std::env::temp_dir()
+                .join(format!("whatsapp-profile-test-{}", std::process::id()))
+                .join(name);
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            dir
+        }
+
+        /// A `secret-tool` keeping its one secret in `keyring` next to it and
+        /// logging its arguments to `calls`; `store` runs `store_body`
+        fn stub_secret_tool(dir: &Path, store_body: &str) -> String {
+            let script = dir.join("secret-tool");
+            fs::write(
+                &script,
+                format!(
+                    "#!/bin/sh
+                     dir=$(dirname \"$0\")
+                     echo \"$@\" >> \"$dir/calls\"
+                     case \"$1\" in
+                     lookup) [ -f \"$dir/keyring\" ] || exit 1; cat \"$dir/keyring\" ;;
+                     store) {} ;;
+                     esac
+",
+                    store_body
+                ),
+            )
+            .unwrap();
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+            script.display().to_string()
+        }
+
+        const KEEPS: &str = "cat > \"$dir/keyring\"";
+
+        #[test]
+        fn new_profile_gets_a_stored_random_key() {
+            let dir = temp_dir("new");
+            let secret_tool = stub_secret_tool(&dir, KEEPS);
+
+            let key = keyring_key(&secret_tool, true).unwrap();
+            use base64::Engine;
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(&key)
+                .unwrap();
+            assert_eq!(bytes.len(), 32);
+            // Stored without a trailing newline
+            assert_eq!(fs::read_to_string(dir.join("keyring")).unwrap(), key);
+
+            // Found again on the next start, and a new profile gets a new key
+            assert_eq!(keyring_key(&secret_tool, false).unwrap(), key);
+            fs::remove_file(dir.join("keyring")).unwrap();
+            assert_ne!(keyring_key(&secret_tool, true).unwrap(), key);
+
+            let calls = fs::read_to_string(dir.join("calls")).unwrap();
+            assert!(calls
+                .lines()
+                .all(|call| call.ends_with("application whatsapp-desktop type profile-key")));
+            assert!(calls.contains("store --label=WhatsApp Desktop profile key"));
+        }
+
+        #[test]
+        fn existing_key_is_used() {
+            let dir = temp_dir("existing");
+            let secret_tool = stub_secret_tool(&dir, "exit 1");
+            fs::write(dir.join("keyring"), "c2VjcmV0\n").unwrap();
+
+            assert_eq!(keyring_key(&secret_tool, false).unwrap(), "c2VjcmV0");
+            assert_eq!(keyring_key(&secret_tool, true).unwrap(), "c2VjcmV0");
+        }
+
+        #[test]
+        fn missing_key_for_an_existing_profile_fails() {
+            let dir = temp_dir("missing");
+            let secret_tool = stub_secret_tool(&dir, KEEPS);
+
+            let error = keyring_key(&secret_tool, false).unwrap_err();
+            assert!(error.contains("missing from the keyring"), "{}", error);
+            // Nothing new was stored over it
+            assert!(!dir.join("keyring").exists());
+        }
+
+        #[test]
+        fn keyring_refusing_the_key_fails() {
+            // A locked keyring, or one whose unlock prompt was dismissed
+            let dir = temp_dir("locked");
+            let secret_tool = stub_secret_tool(&dir, "cat > /dev/null; exit 1");
+            let error = keyring_key(&secret_tool, true).unwrap_err();
+            assert!(error.contains("secret-tool store failed"), "{}", error);
+
+            // Claims to store, but doesn't keep it
+            let dir = temp_dir("forgetful");
+            let secret_tool = stub_secret_tool(&dir, "cat > /dev/null");
+            let error = keyring_key(&secret_tool, true).unwrap_err();
+            assert!(error.contains("didn't keep"), "{}", error);
+        }
+
+        #[test]
+        fn migrates_and_wipes_the_plaintext_profile() {
+            let dir = temp_dir("migrate");
+            let (base, mount) = (dir.join("base"), dir.join("mount"));
+            fs::create_dir_all(base.join("webdata").join("IndexedDB")).unwrap();
+            fs::create_dir_all(&mount).unwrap();
+            fs::write(base.join("webdata").join("IndexedDB").join("db"), "session").unwrap();
+            fs::write(base.join("cookies.sqlite"), "cookies").unwrap();
+            fs::write(base.join("cookies.sqlite-wal"), "recent cookies").unwrap();
+            fs::write(base.join("config.json"), "{}").unwrap();
+
+            // A second name for the cookie store's blocks shows they were
+            // overwritten, not just unlinked
+            fs::hard_link(base.join("cookies.sqlite"), dir.join("blocks")).unwrap();
+
+            migrate(&base, &mount);
+
+            let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+            assert_eq!(
+                read(mount.join("webdata").join("IndexedDB").join("db")),
+                "session"
+            );
+            assert_eq!(read(mount.join("cookies.sqlite")), "cookies");
+            assert_eq!(read(mount.join("cookies.sqlite-wal")), "recent cookies");
+            assert!(!base.join("webdata").exists());
+            assert!(!base.join("cookies.sqlite").exists());
+            assert!(!base.join("cookies.sqlite-wal").exists());
+            assert_eq!(
+                fs::read(dir.join("blocks")).unwrap(),
+                vec![0; "cookies".len()]
+            );
+            // Only the profile moves
+            assert!(base.join("config.json").exists());
+            assert!(!mount.join("config.json").exists());
+        }
+
+        #[test]
+        fn missing_secret_tool_fails() {
+            let dir = temp_dir("uninstalled");
+            let error =
+                keyring_key(&dir.join("secret-tool").display().to_string(), true).unwrap_err();
+            assert!(error.contains("libsecret-tools"), "{}", error);
+        }
+    }
+}
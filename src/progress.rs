@@ -0,0 +1,298 @@
+//! In-page download progress panel
+//!
+//! Rust owns the state: [`DownloadTracker`] follows every WebKit download
+//! and pushes the list to the injected panel with
+//! `window.__downloadProgress.update(items)`. The panel's Cancel, Retry and
+//! dismiss buttons come back over IPC as [`DownloadCommand`]s.
+
+use serde::Deserialize;
+
+/// What the progress panel asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum DownloadCommand {
+    Cancel(u64),
+    Retry(u64),
+    Dismiss(u64),
+}
+
+#[cfg(target_os = "linux")]
+pub use tracker::DownloadTracker;
+
+#[cfg(target_os = "linux")]
+mod tracker {
+    use super::DownloadCommand;
+    use std::cell::{Cell, RefCell};
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+    use webkit2gtk::{DownloadExt, WebViewExt};
+
+    /// Minimum time between two panel updates while data is arriving
+    const PUSH_INTERVAL: Duration = Duration::from_millis(250);
+    /// Weight of the newest sample in the smoothed speed
+    const SPEED_SMOOTHING: f64 = 0.3;
+
+    struct Tracked {
+        download: webkit2gtk::Download,
+        url: String,
+        name: String,
+        received: u64,
+        total: Option<u64>,
+        /// Smoothed bytes per second
+        speed: f64,
+        sample: (Instant, u64),
+        /// Error message once the download failed
+        error: Option<String>,
+        /// Cancelled from the panel, so the failure isn't shown
+        cancelled: bool,
+    }
+
+    /// Active and failed downloads; lives on the main thread
+    pub struct DownloadTracker {
+        webview: webkit2gtk::WebView,
+        downloads: RefCell<BTreeMap<u64, Tracked>>,
+        next_id: Cell<u64>,
+        last_push: Cell<Option<Instant>>,
+    }
+
+    impl DownloadTracker {
+        pub fn new(webview: webkit2gtk::WebView) -> Rc<Self> {
+            Rc::new(Self {
+                webview,
+                downloads: RefCell::default(),
+                next_id: Cell::new(0),
+                last_push: Cell::new(None),
+            })
+        }
+
+        /// Start following a download and return its id
+        pub fn start(&self, download: &webkit2gtk::Download, url: &str) -> u64 {
+            let id = self.next_id.get();
+            self.next_id.set(id + 1);
+
+            self.downloads.borrow_mut().insert(
+                id,
+                Tracked {
+                    download: download.clone(),
+                    url: url.to_string(),
+                    name: String::new(),
+                    received: 0,
+                    total: None,
+                    speed: 0.0,
+                    sample: (Instant::now(), 0),
+                    error: None,
+                    cancelled: false,
+                },
+            );
+            id
+        }
+
+        /// The file name is known once the destination was decided
+        pub fn set_name(&self, id: u64, name: &str) {
+            if let Some(tracked) = self.downloads.borrow_mut().get_mut(&id) {
+                tracked.name = name.to_string();
+            }
+            self.push();
+        }
+
+        pub fn progress(&self, id: u64, received: u64, total: Option<u64>) {
+            if let Some(tracked) = self.downloads.borrow_mut().get_mut(&id) {
+                let now = Instant::now();
+                let elapsed = now.duration_since(tracked.sample.0).as_secs_f64();
+                if elapsed >= PUSH_INTERVAL.as_secs_f64() {
+                    let current = received.saturating_sub(tracked.sample.1) as f64 / elapsed;
+                    tracked.speed = if tracked.speed == 0.0 {
+                        current
+                    } else {
+                        SPEED_SMOOTHING * current + (1.0 - SPEED_SMOOTHING) * tracked.speed
+                    };
+                    tracked.sample = (now, received);
+                }
+                tracked.received = received;
+                tracked.total = total;
+            }
+
+            let due = self
+                .last_push
+                .get()
+                .map(|last| last.elapsed() >= PUSH_INTERVAL)
+                .unwrap_or(true);
+            if due {
+                self.push();
+            }
+        }
+
+        /// Keep a failed download in the panel so it can be retried
+        pub fn fail(&self, id: u64, error: &str) {
+            {
+                let mut downloads = self.downloads.borrow_mut();
+                let cancelled = downloads.get(&id).map(|tracked| tracked.cancelled);
+                if cancelled == Some(true) {
+                    downloads.remove(&id);
+                } else if let Some(tracked) = downloads.get_mut(&id) {
+                    tracked.error = Some(error.to_string());
+                }
+            }
+            self.push();
+        }
+
+        /// Stop showing a download (finished, or refused up front)
+        pub fn remove(&self, id: u64) {
+            self.downloads.borrow_mut().remove(&id);
+            self.push();
+        }
+
+        /// Remove a finished download; failed ones stay until dismissed
+        pub fn finish(&self, id: u64) {
+            let failed = self
+                .downloads
+                .borrow()
+                .get(&id)
+                .map(|tracked| tracked.error.is_some())
+                .unwrap_or(false);
+            if !failed {
+                self.remove(id);
+            }
+        }
+
+        /// Carry out a button press from the panel
+        pub fn run_command(&self, command: DownloadCommand) {
+            match command {
+                DownloadCommand::Cancel(id) => {
+                    let download = self.downloads.borrow_mut().get_mut(&id).map(|tracked| {
+                        tracked.cancelled = true;
+                        tracked.download.clone()
+                    });
+                    if let Some(download) = download {
+                        eprintln!("[DOWNLOAD] Cancelled by user: #{}", id);
+                        download.cancel();
+                    }
+                }
+                DownloadCommand::Retry(id) => {
+                    let url = self.downloads.borrow_mut().remove(&id).map(|t| t.url);
+                    if let Some(url) = url {
+                        eprintln!("[DOWNLOAD] Retrying: {}", url);
+                        // Goes through download-started again like any download
+                        self.webview.download_uri(&url);
+                    }
+                    self.push();
+                }
+                DownloadCommand::Dismiss(id) => self.remove(id),
+            }
+        }
+
+        /// Send the current list to the panel
+        fn push(&self) {
+            self.last_push.set(Some(Instant::now()));
+
+            let items: Vec<_> = self
+                .downloads
+                .borrow()
+                .iter()
+                .map(|(id, tracked)| {
+                    let name = if tracked.name.is_empty() {
+                        &tracked.url
+                    } else {
+                        &tracked.name
+                    };
+                    serde_json::json!({
+                        "id": id,
+                        "name": name,
+                        "received": tracked.received,
+                        "total": tracked.total,
+                        "speed": tracked.speed.round() as u64,
+                        "error": tracked.error,
+                    })
+                })
+                .collect();
+            let json = serde_json::to_string(&items).unwrap_or_else(|_| "[]".to_string());
+
+            let script = format!(
+                "window.__downloadProgress && window.__downloadProgress.update({});",
+                json
+            );
+            self.webview
+                .run_javascript(&script, None::<&gio::Cancellable>, |_| {});
+        }
+    }
+}
+
+/// Injected into every page: the progress panel, filled from Rust
+pub const PROGRESS_OVERLAY_SCRIPT: &str = r#"
+(function() {
+    'use strict';
+
+    const send = (message) => window.ipc.postMessage(JSON.stringify(message));
+
+    function formatSize(bytes) {
+        if (bytes < 1024) return bytes + ' B';
+        if (bytes < 1024 * 1024) return Math.round(bytes / 1024) + ' KB';
+        if (bytes < 1024 * 1024 * 1024) return (bytes / (1024 * 1024)).toFixed(1) + ' MB';
+        return (bytes / (1024 * 1024 * 1024)).toFixed(2) + ' GB';
+    }
+
+    function button(label, message) {
+        const b = document.createElement('button');
+        b.textContent = label;
+        b.style.cssText = 'background:#2a3942;color:#e9edef;border:none;border-radius:6px;padding:3px 8px;cursor:pointer;font-size:12px;';
+        b.onclick = () => send({ Download: message });
+        return b;
+    }
+
+    function update(items) {
+        let panel = document.getElementById('download-progress');
+        if (items.length === 0) {
+            if (panel) panel.remove();
+            return;
+        }
+        if (!panel) {
+            panel = document.createElement('div');
+            panel.id = 'download-progress';
+            panel.style.cssText = 'position:fixed;bottom:20px;right:20px;width:320px;background:#111b21;color:#e9edef;border-radius:12px;padding:8px 12px;z-index:999999;font-size:13px;box-shadow:0 4px 20px rgba(0,0,0,0.4);';
+            document.body.appendChild(panel);
+        }
+        panel.replaceChildren();
+
+        for (const item of items) {
+            const row = document.createElement('div');
+            row.style.cssText = 'padding:6px 0;';
+
+            const head = document.createElement('div');
+            head.style.cssText = 'display:flex;align-items:center;gap:6px;';
+            const name = document.createElement('div');
+            name.style.cssText = 'flex:1;min-width:0;white-space:nowrap;overflow:hidden;text-overflow:ellipsis;';
+            name.textContent = item.name;
+            name.title = item.name;
+            head.appendChild(name);
+
+            const details = document.createElement('div');
+            details.style.cssText = 'font-size:11px;color:#8696a0;margin-top:2px;';
+
+            if (item.error) {
+                head.append(button('Retry', { Retry: item.id }), button('×', { Dismiss: item.id }));
+                details.style.color = '#ff6b6b';
+                details.textContent = 'Failed: ' + item.error;
+                row.append(head, details);
+            } else {
+                head.appendChild(button('Cancel', { Cancel: item.id }));
+
+                const bar = document.createElement('div');
+                bar.style.cssText = 'height:4px;background:#2a3942;border-radius:2px;margin-top:4px;overflow:hidden;';
+                const fill = document.createElement('div');
+                const percent = item.total ? Math.min(100, item.received * 100 / item.total) : 0;
+                fill.style.cssText = 'height:100%;background:#25D366;width:' + percent + '%;';
+                bar.appendChild(fill);
+
+                details.textContent = formatSize(item.received) +
+                    (item.total ? ' of ' + formatSize(item.total) : '') +
+                    (item.speed ? ' · ' + formatSize(item.speed) + '/s' : '');
+                row.append(head, bar, details);
+            }
+
+            panel.appendChild(row);
+        }
+    }
+
+    window.__downloadProgress = { update };
+})();
+"#;
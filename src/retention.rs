@@ -0,0 +1,450 @@
+//! Retention policy for downloaded media
+//!
+//! Deletes downloads older than `max_age_days`, then the oldest ones until
+//! everything fits in `max_total_gb`. Only files recorded in the download
+//! history are considered, and only while they still have the size they were
+//! downloaded with, so files the user put in the downloads folder (or
+//! overwrote) are never touched. In dry-run mode nothing is deleted and the
+//! report only lists what would be.
+
+use crate::history::{History, HistoryEntry};
+use crate::{Config, UserEvent};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tao::event_loop::EventLoopProxy;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+const SECS_PER_YEAR: u64 = 365 * SECS_PER_DAY;
+const BYTES_PER_GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// Retention settings, all off by default
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    /// Delete downloads older than this many days
+    pub max_age_days: Option<u64>,
+    /// Keep at most this many gigabytes of downloads
+    pub max_total_gb: Option<f64>,
+    /// Only report what would be deleted
+    pub dry_run: bool,
+    /// Hours between runs while the app is open
+    pub interval_hours: u64,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_age_days: None,
+            max_total_gb: None,
+            dry_run: false,
+            interval_hours: 6,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_age_days.is_some() || self.max_total_gb.is_some()
+    }
+
+    /// Time between periodic runs
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_hours.max(1).saturating_mul(60 * 60))
+    }
+}
+
+/// When to run again after `interval`. Intervals too long for the clock wait
+/// a year instead; the app is restarted long before that.
+pub fn next_run(interval: Duration) -> Instant {
+    let now = Instant::now();
+    now.checked_add(interval)
+        .or_else(|| now.checked_add(Duration::from_secs(SECS_PER_YEAR)))
+        .unwrap_or(now)
+}
+
+/// Why a file is due for deletion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    TooOld,
+    OverQuota,
+}
+
+/// A file the policy selected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expired {
+    pub path: PathBuf,
+    pub size: u64,
+    pub reason: Reason,
+}
+
+/// Outcome of one retention run
+#[derive(Debug, Clone, Default)]
+pub struct RetentionReport {
+    pub dry_run: bool,
+    /// Files deleted, or that would be in a dry run
+    pub files: Vec<Expired>,
+    pub bytes: u64,
+}
+
+/// A downloaded file as it is on disk now
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Newest download of this file, seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+/// Files from the history that still exist unchanged. Deduplicated
+/// downloads share a file; it counts once, as old as its newest download.
+pub fn candidates(entries: &[HistoryEntry]) -> Vec<Candidate> {
+    let mut by_path: HashMap<&PathBuf, Candidate> = HashMap::new();
+
+    for entry in entries {
+        let Ok(meta) = fs::metadata(&entry.path) else {
+            continue;
+        };
+        if !meta.is_file() || meta.len() != entry.size {
+            continue;
+        }
+
+        by_path
+            .entry(&entry.path)
+            .and_modify(|c| c.timestamp = c.timestamp.max(entry.timestamp))
+            .or_insert_with(|| Candidate {
+                path: entry.path.clone(),
+                size: entry.size,
+                timestamp: entry.timestamp,
+            });
+    }
+
+    by_path.into_values().collect()
+}
+
+/// Pick the files to delete under `policy` at time `now`
+pub fn plan(mut candidates: Vec<Candidate>, policy: &RetentionPolicy, now: u64) -> Vec<Expired> {
+    // Oldest first
+    candidates.sort_by_key(|c| c.timestamp);
+
+    let mut expired = Vec::new();
+    let mut kept = Vec::new();
+
+    for candidate in candidates {
+        let age = now.saturating_sub(candidate.timestamp);
+        match policy.max_age_days {
+            Some(days) if age > days.saturating_mul(SECS_PER_DAY) => expired.push(Expired {
+                path: candidate.path,
+                size: candidate.size,
+                reason: Reason::TooOld,
+            }),
+            _ => kept.push(candidate),
+        }
+    }
+
+    if let Some(gb) = policy.max_total_gb {
+        let quota = (gb.max(0.0) * BYTES_PER_GB) as u64;
+        let mut total: u64 = kept.iter().map(|c| c.size).sum();
+
+        for candidate in kept {
+            if total <= quota {
+                break;
+            }
+            total -= candidate.size;
+            expired.push(Expired {
+                path: candidate.path,
+                size: candidate.size,
+                reason: Reason::OverQuota,
+            });
+        }
+    }
+
+    expired
+}
+
+/// Apply the policy to the downloads in the history
+pub fn run(policy: &RetentionPolicy, history: &Mutex<History>) -> RetentionReport {
+    let candidates = match history.lock() {
+        Ok(history) => candidates(&history.entries),
+        Err(_) => return RetentionReport::default(),
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let mut report = RetentionReport {
+        dry_run: policy.dry_run,
+        ..Default::default()
+    };
+
+    let (deleted, freed) = if policy.dry_run {
+        ("Would delete", "Would free")
+    } else {
+        ("Deleted", "Freed")
+    };
+
+    for file in plan(candidates, policy, now) {
+        if !policy.dry_run {
+            if let Err(e) = fs::remove_file(&file.path) {
+                eprintln!("[RETENTION] Failed to delete {:?}: {}", file.path, e);
+                continue;
+            }
+        }
+        eprintln!(
+            "[RETENTION] {} {:?} ({} bytes, {:?})",
+            deleted, file.path, file.size, file.reason
+        );
+
+        report.bytes += file.size;
+        report.files.push(file);
+    }
+
+    eprintln!(
+        "[RETENTION] {} {} bytes in {} files",
+        freed,
+        report.bytes,
+        report.files.len()
+    );
+
+    report
+}
+
+/// Run the policy on a background thread if it is enabled, reporting back to
+/// the event loop. Returns the time until the next run.
+pub fn spawn(
+    config: &Mutex<Config>,
+    history: Arc<Mutex<History>>,
+    proxy: EventLoopProxy<UserEvent>,
+) -> Duration {
+    let policy = config
+        .lock()
+        .map(|cfg| cfg.retention.clone())
+        .unwrap_or_default();
+    let interval = policy.interval();
+    if !policy.is_enabled() {
+        return interval;
+    }
+
+    let spawned = thread::Builder::new()
+        .name("retention".to_string())
+        .spawn(move || {
+            let report = run(&policy, &history);
+            let _ = proxy.send_event(UserEvent::RetentionDone(report));
+        });
+    if let Err(e) = spawned {
+        eprintln!("[RETENTION] Failed to start: {}", e);
+    }
+
+    interval
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postprocess::AfterDownload;
+    use crate::sniff::Category;
+    use std::path::Path;
+
+    const DAY: u64 = SECS_PER_DAY;
+    const NOW: u64 = 1000 * DAY;
+
+    fn candidate(name: &str, size: u64, timestamp: u64) -> Candidate {
+        Candidate {
+            path: PathBuf::from(name),
+            size,
+            timestamp,
+        }
+    }
+
+    fn entry(path: &Path, size: u64, timestamp: u64) -> HistoryEntry {
+        HistoryEntry {
+            id: 0,
+            url: String::new(),
+            path: path.to_path_buf(),
+            kind: None,
+            category: Category::Other,
+            size,
+            timestamp,
+            after: AfterDownload::Nothing,
+            action: None,
+            hash: None,
+            duplicate_of: None,
+        }
+    }
+
+    /// A fresh directory for one test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("whatsapp-retention-test-{}", std::process::id()))
+            .join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn names(expired: &[Expired]) -> Vec<(&str, Reason)> {
+        expired
+            .iter()
+            .map(|e| (e.path.to_str().unwrap(), e.reason))
+            .collect()
+    }
+
+    #[test]
+    fn plan_deletes_files_past_the_age() {
+        let policy = RetentionPolicy {
+            max_age_days: Some(30),
+            ..Default::default()
+        };
+        let files = vec![
+            candidate("new", 1, NOW - DAY),
+            candidate("limit", 1, NOW - 30 * DAY),
+            candidate("old", 1, NOW - 30 * DAY - 1),
+            candidate("older", 1, NOW - 400 * DAY),
+            // Clock skew: from the future
+            candidate("future", 1, NOW + DAY),
+        ];
+
+        assert_eq!(
+            names(&plan(files, &policy, NOW)),
+            [("older", Reason::TooOld), ("old", Reason::TooOld)]
+        );
+    }
+
+    #[test]
+    fn plan_evicts_oldest_first_over_the_quota() {
+        let policy = RetentionPolicy {
+            // 25 bytes
+            max_total_gb: Some(25.0 / BYTES_PER_GB),
+            ..Default::default()
+        };
+        let files = vec![
+            candidate("b", 10, NOW - 2 * DAY),
+            candidate("d", 10, NOW),
+            candidate("a", 10, NOW - 3 * DAY),
+            candidate("c", 10, NOW - DAY),
+        ];
+
+        assert_eq!(
+            names(&plan(files, &policy, NOW)),
+            [("a", Reason::OverQuota), ("b", Reason::OverQuota)]
+        );
+
+        let within = vec![candidate("a", 10, 0), candidate("b", 15, 0)];
+        assert!(plan(within, &policy, NOW).is_empty());
+    }
+
+    #[test]
+    fn plan_applies_age_then_quota() {
+        let policy = RetentionPolicy {
+            max_age_days: Some(7),
+            max_total_gb: Some(25.0 / BYTES_PER_GB),
+            ..Default::default()
+        };
+        let files = vec![
+            candidate("ancient", 100, NOW - 30 * DAY),
+            candidate("a", 10, NOW - 3 * DAY),
+            candidate("b", 10, NOW - 2 * DAY),
+            candidate("c", 10, NOW - DAY),
+        ];
+
+        // The old file goes for its age and no longer counts towards the
+        // quota; of the rest, the oldest goes to get under 25 bytes
+        assert_eq!(
+            names(&plan(files, &policy, NOW)),
+            [("ancient", Reason::TooOld), ("a", Reason::OverQuota)]
+        );
+    }
+
+    #[test]
+    fn candidates_are_unchanged_files() {
+        let dir = temp_dir("candidates");
+        let (kept, changed, shared) = (dir.join("kept"), dir.join("changed"), dir.join("shared"));
+        fs::write(&kept, "12345").unwrap();
+        fs::write(&changed, "overwritten by the user").unwrap();
+        fs::write(&shared, "123").unwrap();
+
+        let entries = vec![
+            entry(&kept, 5, 100),
+            entry(&changed, 5, 100),
+            entry(&dir.join("gone"), 5, 100),
+            // Deduplicated downloads of one file
+            entry(&shared, 3, 300),
+            entry(&shared, 3, 900),
+            entry(&shared, 3, 500),
+            entry(&dir, 0, 100),
+        ];
+
+        let mut found = candidates(&entries);
+        found.sort_by_key(|c| c.timestamp);
+        let found: Vec<_> = found
+            .iter()
+            .map(|c| (&c.path, c.size, c.timestamp))
+            .collect();
+        assert_eq!(found, [(&kept, 5, 100), (&shared, 3, 900)]);
+    }
+
+    #[test]
+    fn dry_run_deletes_nothing() {
+        let dir = temp_dir("dry-run");
+        let file = dir.join("old.jpg");
+        fs::write(&file, "photo").unwrap();
+        let mut history = History::default();
+        history.entries.push(entry(&file, 5, 0));
+        let history = Mutex::new(history);
+
+        let dry_run = RetentionPolicy {
+            max_age_days: Some(1),
+            dry_run: true,
+            ..Default::default()
+        };
+        let report = run(&dry_run, &history);
+        assert!(report.dry_run);
+        assert_eq!(report.bytes, 5);
+        assert_eq!(
+            names(&report.files),
+            [(file.to_str().unwrap(), Reason::TooOld)]
+        );
+        assert!(file.exists());
+
+        let report = run(
+            &RetentionPolicy {
+                dry_run: false,
+                ..dry_run
+            },
+            &history,
+        );
+        assert_eq!(report.files.len(), 1);
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn huge_settings_dont_overflow() {
+        let policy = RetentionPolicy {
+            max_age_days: Some(u64::MAX),
+            max_total_gb: Some(f64::MAX),
+            interval_hours: u64::MAX,
+            ..Default::default()
+        };
+        let now = 1_700_000_000;
+        let files = vec![candidate("old", 10, 0), candidate("new", 10, now)];
+
+        assert!(plan(files, &policy, now).is_empty());
+        assert_eq!(policy.interval(), Duration::from_secs(u64::MAX));
+        assert!(next_run(policy.interval()) > Instant::now());
+    }
+
+    #[test]
+    fn interval_is_at_least_an_hour() {
+        let policy = RetentionPolicy {
+            interval_hours: 0,
+            ..Default::default()
+        };
+        assert_eq!(policy.interval(), Duration::from_secs(60 * 60));
+    }
+}
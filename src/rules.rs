@@ -0,0 +1,379 @@
+//! Per-file-type "open with" rules
+//!
+//! `Config::open_rules` is an ordered list; the first rule whose matcher fits a
+//! downloaded file decides what happens to it. The built-in defaults mirror the
+//! original hardcoded behavior: ask for a browser for PDFs, ask for a document
+//! handler for office files and hand everything else to the system.
+
+use crate::Browser;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// What a rule is matched against
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RuleMatch {
+    /// File extension, case-insensitive, without the leading dot
+    Extension(Vec<String>),
+    /// MIME type, either exact (`application/pdf`) or a `type/*` wildcard
+    Mime(String),
+    /// Type detected from the file contents, named by its usual extension
+    Sniffed(Vec<String>),
+    /// Matches every file, used as the catch-all at the end of the list
+    Any,
+}
+
+/// What to do with a file once a rule matched
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OpenAction {
+    /// Open with the system default application (xdg-open)
+    System,
+    /// Open with a specific browser
+    Browser(Browser),
+    /// Run a command; `{path}` is replaced by the file path
+    Command(String),
+    /// Show the file in the file manager
+    Reveal,
+    /// Leave the file where it was saved
+    Nothing,
+    /// Use the saved PDF browser, or ask for one
+    AskBrowser,
+    /// Use the saved document handler, or ask for one
+    AskDocHandler,
+}
+
+/// A single matcher -> action pair
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenRule {
+    pub when: RuleMatch,
+    pub action: OpenAction,
+}
+
+/// Everything known about a downloaded file that rules can match on
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileFacts {
+    /// Lowercase extension without the dot, empty if there is none
+    pub extension: String,
+    /// MIME type, if known
+    pub mime: Option<String>,
+    /// Extension of the type detected from the file contents, if any
+    pub sniffed: Option<String>,
+}
+
+impl FileFacts {
+    /// Facts derivable from the path alone
+    pub fn from_path(path: &Path) -> Self {
+        Self {
+            extension: path
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or("")
+                .to_lowercase(),
+            mime: None,
+            sniffed: None,
+        }
+    }
+}
+
+impl RuleMatch {
+    pub fn matches(&self, facts: &FileFacts) -> bool {
+        match self {
+            RuleMatch::Extension(exts) => exts.iter().any(|e| {
+                e.trim_start_matches('.')
+                    .eq_ignore_ascii_case(&facts.extension)
+            }),
+            RuleMatch::Mime(pattern) => facts
+                .mime
+                .as_deref()
+                .map(|mime| mime_matches(pattern, mime))
+                .unwrap_or(false),
+            RuleMatch::Sniffed(kinds) => facts
+                .sniffed
+                .as_deref()
+                .map(|sniffed| kinds.iter().any(|k| k.eq_ignore_ascii_case(sniffed)))
+                .unwrap_or(false),
+            RuleMatch::Any => true,
+        }
+    }
+}
+
+impl OpenRule {
+    pub fn new(when: RuleMatch, action: OpenAction) -> Self {
+        Self { when, action }
+    }
+
+    pub fn matches(&self, facts: &FileFacts) -> bool {
+        self.when.matches(facts)
+    }
+}
+
+/// Match a MIME type against an exact type or a `type/*` wildcard
+fn mime_matches(pattern: &str, mime: &str) -> bool {
+    // Ignore parameters such as "; charset=utf-8"
+    let mime = mime.split(';').next().unwrap_or("").trim();
+    let pattern = pattern.trim();
+
+    if pattern == "*" || pattern == "*/*" {
+        return true;
+    }
+
+    match pattern.strip_suffix("/*") {
+        Some(major) => mime
+            .split('/')
+            .next()
+            .map(|m| m.eq_ignore_ascii_case(major))
+            .unwrap_or(false),
+        None => pattern.eq_ignore_ascii_case(mime),
+    }
+}
+
+/// Find the action of the first rule matching the file
+pub fn find_action<'a>(rules: &'a [OpenRule], facts: &FileFacts) -> Option<&'a OpenAction> {
+    rules.iter().find(|r| r.matches(facts)).map(|r| &r.action)
+}
+
+/// Split a command template into program and arguments, substituting `{path}`.
+/// The path is appended as the last argument if the template doesn't mention it.
+pub fn expand_command(template: &str, path: &Path) -> Vec<String> {
+    let path = path.to_string_lossy();
+    let mut args: Vec<String> = template
+        .split_whitespace()
+        .map(|arg| arg.replace("{path}", &path))
+        .collect();
+
+    if !args.is_empty() && !template.contains("{path}") {
+        args.push(path.into_owned());
+    }
+
+    args
+}
+
+/// The built-in rule set, equivalent to the original hardcoded behavior
+pub fn default_rules() -> Vec<OpenRule> {
+    let exts = |list: &[&str]| list.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+
+    vec![
+        OpenRule::new(RuleMatch::Extension(exts(&["pdf"])), OpenAction::AskBrowser),
+        OpenRule::new(
+            RuleMatch::Extension(exts(&[
+                "odt", "odp", "ods", "doc", "docx", "xls", "xlsx", "ppt", "pptx",
+            ])),
+            OpenAction::AskDocHandler,
+        ),
+        OpenRule::new(RuleMatch::Any, OpenAction::System),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts(extension: &str, mime: Option<&str>, sniffed: Option<&str>) -> FileFacts {
+        FileFacts {
+            extension: extension.to_string(),
+            mime: mime.map(str::to_string),
+            sniffed: sniffed.map(str::to_string),
+        }
+    }
+
+    fn list(items: &[&str]) -> Vec<String> {
+        items.iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn matchers() {
+        let cases = [
+            (
+                RuleMatch::Extension(list(&["pdf"])),
+                facts("pdf", None, None),
+                true,
+            ),
+            (
+                RuleMatch::Extension(list(&[".PDF"])),
+                facts("pdf", None, None),
+                true,
+            ),
+            (
+                RuleMatch::Extension(list(&["pdf", "txt"])),
+                facts("txt", None, None),
+                true,
+            ),
+            (
+                RuleMatch::Extension(list(&["pdf"])),
+                facts("", None, None),
+                false,
+            ),
+            (
+                RuleMatch::Extension(list(&["pdf"])),
+                facts("pdfx", None, None),
+                false,
+            ),
+            (
+                RuleMatch::Mime("application/pdf".into()),
+                facts("", Some("application/pdf"), None),
+                true,
+            ),
+            (
+                RuleMatch::Mime("Application/PDF".into()),
+                facts("", Some("application/pdf"), None),
+                true,
+            ),
+            (
+                RuleMatch::Mime("text/plain".into()),
+                facts("", Some("text/plain; charset=utf-8"), None),
+                true,
+            ),
+            (
+                RuleMatch::Mime("image/*".into()),
+                facts("", Some("image/webp"), None),
+                true,
+            ),
+            (
+                RuleMatch::Mime("image/*".into()),
+                facts("", Some("video/mp4"), None),
+                false,
+            ),
+            (
+                RuleMatch::Mime("*/*".into()),
+                facts("", Some("video/mp4"), None),
+                true,
+            ),
+            (
+                RuleMatch::Mime("image/*".into()),
+                facts("png", None, None),
+                false,
+            ),
+            (
+                RuleMatch::Sniffed(list(&["docx", "xlsx"])),
+                facts("zip", None, Some("xlsx")),
+                true,
+            ),
+            (
+                RuleMatch::Sniffed(list(&["pdf"])),
+                facts("pdf", None, None),
+                false,
+            ),
+            (
+                RuleMatch::Sniffed(list(&["pdf"])),
+                facts("pdf", None, Some("zip")),
+                false,
+            ),
+            (RuleMatch::Any, facts("", None, None), true),
+        ];
+
+        for (matcher, facts, expected) in cases {
+            assert_eq!(
+                matcher.matches(&facts),
+                expected,
+                "{:?} on {:?}",
+                matcher,
+                facts
+            );
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = vec![
+            OpenRule::new(
+                RuleMatch::Sniffed(list(&["pdf"])),
+                OpenAction::Browser(Browser::Firefox),
+            ),
+            OpenRule::new(RuleMatch::Mime("image/*".into()), OpenAction::Nothing),
+            OpenRule::new(RuleMatch::Extension(list(&["pdf"])), OpenAction::Reveal),
+            OpenRule::new(RuleMatch::Any, OpenAction::System),
+        ];
+
+        let cases = [
+            (
+                facts("pdf", Some("application/pdf"), Some("pdf")),
+                OpenAction::Browser(Browser::Firefox),
+            ),
+            // Named .pdf but not one: the sniffed rule doesn't apply
+            (facts("pdf", None, Some("zip")), OpenAction::Reveal),
+            (
+                facts("jpg", Some("image/jpeg"), Some("jpg")),
+                OpenAction::Nothing,
+            ),
+            (
+                facts("zip", Some("application/zip"), Some("zip")),
+                OpenAction::System,
+            ),
+        ];
+
+        for (facts, action) in cases {
+            assert_eq!(find_action(&rules, &facts), Some(&action), "{:?}", facts);
+        }
+
+        assert_eq!(find_action(&[], &facts("pdf", None, None)), None);
+    }
+
+    #[test]
+    fn default_rules_keep_the_old_behavior() {
+        let rules = default_rules();
+        let cases = [
+            ("pdf", OpenAction::AskBrowser),
+            ("PDF", OpenAction::AskBrowser),
+            ("docx", OpenAction::AskDocHandler),
+            ("ods", OpenAction::AskDocHandler),
+            ("ppt", OpenAction::AskDocHandler),
+            ("zip", OpenAction::System),
+            ("jpg", OpenAction::System),
+            ("", OpenAction::System),
+        ];
+
+        for (extension, action) in cases {
+            let facts = FileFacts::from_path(Path::new(&format!("/tmp/file.{}", extension)));
+            assert_eq!(find_action(&rules, &facts), Some(&action), "{}", extension);
+        }
+    }
+
+    #[test]
+    fn expands_path_into_command_templates() {
+        let path = Path::new("/home/me/Downloads/Quarterly report (1).pdf");
+        let cases: [(&str, &[&str]); 6] = [
+            (
+                "evince --fullscreen {path}",
+                &[
+                    "evince",
+                    "--fullscreen",
+                    "/home/me/Downloads/Quarterly report (1).pdf",
+                ],
+            ),
+            // Without `{path}` the file is appended
+            (
+                "xdg-open",
+                &["xdg-open", "/home/me/Downloads/Quarterly report (1).pdf"],
+            ),
+            (
+                "  okular   --unique  ",
+                &[
+                    "okular",
+                    "--unique",
+                    "/home/me/Downloads/Quarterly report (1).pdf",
+                ],
+            ),
+            // Substituted inside a larger argument
+            (
+                "tool --input={path} --verbose",
+                &[
+                    "tool",
+                    "--input=/home/me/Downloads/Quarterly report (1).pdf",
+                    "--verbose",
+                ],
+            ),
+            (
+                "cp {path} {path}.bak",
+                &[
+                    "cp",
+                    "/home/me/Downloads/Quarterly report (1).pdf",
+                    "/home/me/Downloads/Quarterly report (1).pdf.bak",
+                ],
+            ),
+            ("", &[]),
+        ];
+
+        for (template, expected) in cases {
+            assert_eq!(expand_command(template, path), expected, "{:?}", template);
+        }
+    }
+}
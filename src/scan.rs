@@ -0,0 +1,272 @@
+//! Malware-scan hook for finished downloads
+//!
+//! When `Config::scan_command` is set, every finished download is passed to
+//! that command before anything else happens to it, e.g.
+//! `clamdscan --fdpass {path}`. Exit codes follow the ClamAV convention:
+//! 0 is clean, 1 means a threat was found, anything else is a scanner error.
+//! Flagged files are moved to the quarantine directory; files that could not
+//! be scanned are kept but not opened.
+
+use crate::downloads;
+use crate::rules::expand_command;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a running scanner is checked for completion
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Outcome of scanning one file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    Clean,
+    /// The scanner flagged the file; holds its output
+    Infected(String),
+    /// The scanner failed, crashed or timed out
+    Failed(String),
+}
+
+/// Scanner settings taken from the config
+#[derive(Debug, Clone)]
+pub struct Scanner {
+    /// Command template; `{path}` is replaced by the file path
+    pub command: String,
+    pub timeout: Duration,
+    pub quarantine_dir: PathBuf,
+}
+
+impl Scanner {
+    /// Scan a file, killing the scanner if it takes too long
+    pub fn scan(&self, path: &Path) -> ScanVerdict {
+        scan(&self.command, path, self.timeout)
+    }
+
+    /// Move a flagged file into the quarantine directory
+    pub fn quarantine(&self, path: &Path) -> io::Result<PathBuf> {
+        quarantine(path, &self.quarantine_dir)
+    }
+}
+
+/// Run the scanner `template` on `path`, killing it after `timeout`
+pub fn scan(template: &str, path: &Path, timeout: Duration) -> ScanVerdict {
+    let args = expand_command(template, path);
+    let Some((program, args)) = args.split_first() else {
+        return ScanVerdict::Failed("empty scan command".to_string());
+    };
+
+    let child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => return ScanVerdict::Failed(format!("cannot run {}: {}", program, e)),
+    };
+
+    // Drain the pipes on their own threads so a chatty scanner can't block
+    let stdout = child.stdout.take().map(read_to_end);
+    let stderr = child.stderr.take().map(read_to_end);
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return ScanVerdict::Failed(format!("timed out after {:?}", timeout));
+            }
+            Err(e) => return ScanVerdict::Failed(e.to_string()),
+        }
+    };
+
+    let output = [stdout, stderr]
+        .into_iter()
+        .flatten()
+        .filter_map(|reader| reader.join().ok())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string();
+
+    match status.code() {
+        Some(0) => ScanVerdict::Clean,
+        Some(1) => ScanVerdict::Infected(output),
+        Some(code) => ScanVerdict::Failed(format!("exit code {}: {}", code, output)),
+        None => ScanVerdict::Failed("scanner was killed by a signal".to_string()),
+    }
+}
+
+fn read_to_end(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        String::from_utf8_lossy(&buf).into_owned()
+    })
+}
+
+/// Default quarantine directory, next to the cookies in the data dir
+pub fn default_quarantine_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("whatsapp-desktop")
+        .join("quarantine")
+}
+
+/// Move a flagged file into `dir` and make it unreadable to others
+pub fn quarantine(path: &Path, dir: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+
+    let filename = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "download".to_string());
+    let dest = downloads::unique_path(dir, &filename, |_| false);
+
+    // rename() fails across filesystems; copy and delete instead
+    if fs::rename(path, &dest).is_err() {
+        fs::copy(path, &dest)?;
+        fs::remove_file(path)?;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&dest, fs::Permissions::from_mode(0o600))?;
+    }
+
+    Ok(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for one test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("whatsapp-scan-test-{}", std::process::id()))
+            .join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A scanner running `body` as a shell script; the file is `$2`, after a
+    /// `--fdpass` like clamdscan's
+    fn stub_scanner(dir: &Path, body: &str, timeout: Duration) -> Scanner {
+        let script = dir.join("scanner.sh");
+        fs::write(&script, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        Scanner {
+            // Run through sh, so the script needn't be executable
+            command: format!("sh {} --fdpass {{path}}", script.display()),
+            timeout,
+            quarantine_dir: dir.join("quarantine"),
+        }
+    }
+
+    fn download(dir: &Path, name: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, b"contents").unwrap();
+        path
+    }
+
+    #[test]
+    fn clean_file() {
+        let dir = temp_dir("clean");
+        let scanner = stub_scanner(&dir, "echo \"$2: OK\"; exit 0", Duration::from_secs(10));
+        let file = download(&dir, "photo.jpg");
+
+        assert_eq!(scanner.scan(&file), ScanVerdict::Clean);
+        assert!(file.exists());
+    }
+
+    #[test]
+    fn infected_file_is_quarantined() {
+        let dir = temp_dir("infected");
+        let scanner = stub_scanner(
+            &dir,
+            "echo \"$2: Eicar-Signature FOUND\"; exit 1",
+            Duration::from_secs(10),
+        );
+        let file = download(&dir, "invoice.pdf");
+
+        let ScanVerdict::Infected(output) = scanner.scan(&file) else {
+            panic!("expected an infected verdict");
+        };
+        assert!(
+            output.contains("invoice.pdf: Eicar-Signature FOUND"),
+            "{}",
+            output
+        );
+
+        let moved = scanner.quarantine(&file).unwrap();
+        assert!(!file.exists());
+        assert_eq!(moved, dir.join("quarantine").join("invoice.pdf"));
+        assert_eq!(fs::read(&moved).unwrap(), b"contents");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&moved).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // A second flagged file with the same name doesn't overwrite the first
+        let again = download(&dir, "invoice.pdf");
+        let moved_again = scanner.quarantine(&again).unwrap();
+        assert_ne!(moved_again, moved);
+        assert!(moved.exists() && moved_again.exists());
+    }
+
+    #[test]
+    fn hanging_scanner_times_out() {
+        let dir = temp_dir("hang");
+        let scanner = stub_scanner(&dir, "exec sleep 30", Duration::from_millis(500));
+        let file = download(&dir, "video.mp4");
+
+        let started = Instant::now();
+        let verdict = scanner.scan(&file);
+
+        assert!(
+            matches!(&verdict, ScanVerdict::Failed(reason) if reason.contains("timed out")),
+            "{:?}",
+            verdict
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(file.exists());
+    }
+
+    #[test]
+    fn scanner_errors_fail() {
+        let dir = temp_dir("error");
+        let file = download(&dir, "notes.txt");
+
+        let broken = stub_scanner(
+            &dir,
+            "echo 'cannot connect' >&2; exit 2",
+            Duration::from_secs(10),
+        );
+        assert!(matches!(
+            broken.scan(&file),
+            ScanVerdict::Failed(reason) if reason.contains("exit code 2") && reason.contains("cannot connect")
+        ));
+
+        let missing = Scanner {
+            command: "/nonexistent/scanner {path}".to_string(),
+            timeout: Duration::from_secs(1),
+            quarantine_dir: dir.join("quarantine"),
+        };
+        assert!(matches!(missing.scan(&file), ScanVerdict::Failed(_)));
+        assert!(matches!(
+            scan("", &file, Duration::from_secs(1)),
+            ScanVerdict::Failed(_)
+        ));
+    }
+}
@@ -0,0 +1,728 @@
+//! Content sniffing for downloaded files
+//!
+//! WhatsApp media URLs rarely carry a useful name, so the real type is read
+//! from the file header. Simple formats are recognized through the `SIGNATURES`
+//! table; ZIP containers are opened far enough to tell OOXML and ODF documents
+//! apart from plain archives.
+
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// How many bytes of the file header are inspected
+const HEADER_LEN: u64 = 8192;
+
+/// Upper bound on the ZIP central directory we are willing to read
+const MAX_CENTRAL_DIRECTORY: u64 = 1024 * 1024;
+
+/// Broad file groups, used for per-type settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Category {
+    Image,
+    Video,
+    Audio,
+    Document,
+    Archive,
+    Other,
+}
+
+impl Category {
+    /// Best guess from the extension alone, for types we can't sniff
+    pub fn from_extension(ext: &str) -> Self {
+        match ext.to_lowercase().as_str() {
+            "jpg" | "jpeg" | "png" | "gif" | "webp" | "bmp" | "svg" | "tif" | "tiff" | "heic"
+            | "heif" | "avif" => Category::Image,
+            "mp4" | "m4v" | "mov" | "mkv" | "webm" | "avi" | "3gp" | "3gpp" => Category::Video,
+            "mp3" | "m4a" | "aac" | "ogg" | "oga" | "opus" | "wav" | "flac" | "amr" => {
+                Category::Audio
+            }
+            "pdf" | "doc" | "docx" | "xls" | "xlsx" | "ppt" | "pptx" | "odt" | "ods" | "odp"
+            | "rtf" | "txt" | "csv" | "md" | "vcf" => Category::Document,
+            "zip" | "rar" | "7z" | "tar" | "gz" | "xz" | "bz2" | "zst" => Category::Archive,
+            _ => Category::Other,
+        }
+    }
+
+    /// Category of a file on disk, from its contents if possible
+    pub fn of(path: &Path) -> Self {
+        match sniff_file(path) {
+            Some(kind) => kind.category(),
+            None => Self::from_extension(path.extension().and_then(|e| e.to_str()).unwrap_or("")),
+        }
+    }
+}
+
+/// File types we can recognize from their contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Png,
+    Jpeg,
+    Gif,
+    WebP,
+    Heic,
+    Avif,
+    Pdf,
+    Docx,
+    Xlsx,
+    Pptx,
+    Odt,
+    Ods,
+    Odp,
+    Zip,
+    Ogg,
+    Opus,
+    Mp3,
+    M4a,
+    Wav,
+    Avi,
+    Mp4,
+    Mov,
+    ThreeGp,
+    Text,
+}
+
+impl FileKind {
+    /// Canonical extension for this type
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileKind::Png => "png",
+            FileKind::Jpeg => "jpg",
+            FileKind::Gif => "gif",
+            FileKind::WebP => "webp",
+            FileKind::Heic => "heic",
+            FileKind::Avif => "avif",
+            FileKind::Pdf => "pdf",
+            FileKind::Docx => "docx",
+            FileKind::Xlsx => "xlsx",
+            FileKind::Pptx => "pptx",
+            FileKind::Odt => "odt",
+            FileKind::Ods => "ods",
+            FileKind::Odp => "odp",
+            FileKind::Zip => "zip",
+            FileKind::Ogg => "ogg",
+            FileKind::Opus => "opus",
+            FileKind::Mp3 => "mp3",
+            FileKind::M4a => "m4a",
+            FileKind::Wav => "wav",
+            FileKind::Avi => "avi",
+            FileKind::Mp4 => "mp4",
+            FileKind::Mov => "mov",
+            FileKind::ThreeGp => "3gp",
+            FileKind::Text => "txt",
+        }
+    }
+
+    pub fn category(&self) -> Category {
+        match self {
+            FileKind::Png
+            | FileKind::Jpeg
+            | FileKind::Gif
+            | FileKind::WebP
+            | FileKind::Heic
+            | FileKind::Avif => Category::Image,
+            FileKind::Avi | FileKind::Mp4 | FileKind::Mov | FileKind::ThreeGp => Category::Video,
+            FileKind::Ogg | FileKind::Opus | FileKind::Mp3 | FileKind::M4a | FileKind::Wav => {
+                Category::Audio
+            }
+            FileKind::Pdf
+            | FileKind::Docx
+            | FileKind::Xlsx
+            | FileKind::Pptx
+            | FileKind::Odt
+            | FileKind::Ods
+            | FileKind::Odp
+            | FileKind::Text => Category::Document,
+            FileKind::Zip => Category::Archive,
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            FileKind::Png => "image/png",
+            FileKind::Jpeg => "image/jpeg",
+            FileKind::Gif => "image/gif",
+            FileKind::WebP => "image/webp",
+            FileKind::Heic => "image/heic",
+            FileKind::Avif => "image/avif",
+            FileKind::Pdf => "application/pdf",
+            FileKind::Docx => {
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+            }
+            FileKind::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            FileKind::Pptx => {
+                "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+            }
+            FileKind::Odt => "application/vnd.oasis.opendocument.text",
+            FileKind::Ods => "application/vnd.oasis.opendocument.spreadsheet",
+            FileKind::Odp => "application/vnd.oasis.opendocument.presentation",
+            FileKind::Zip => "application/zip",
+            FileKind::Ogg => "audio/ogg",
+            FileKind::Opus => "audio/ogg; codecs=opus",
+            FileKind::Mp3 => "audio/mpeg",
+            FileKind::M4a => "audio/mp4",
+            FileKind::Wav => "audio/wav",
+            FileKind::Avi => "video/x-msvideo",
+            FileKind::Mp4 => "video/mp4",
+            FileKind::Mov => "video/quicktime",
+            FileKind::ThreeGp => "video/3gpp",
+            FileKind::Text => "text/plain",
+        }
+    }
+
+    /// Whether `ext` is an acceptable extension for this type, so files like
+    /// `photo.jpeg` or `notes.csv` are not renamed needlessly
+    pub fn accepts_extension(&self, ext: &str) -> bool {
+        let ext = ext.to_lowercase();
+        let aliases: &[&str] = match self {
+            FileKind::Jpeg => &["jpg", "jpeg", "jfif", "jpe"],
+            FileKind::Heic => &["heic", "heif"],
+            FileKind::Docx => &["docx", "docm", "dotx"],
+            FileKind::Xlsx => &["xlsx", "xlsm", "xltx"],
+            FileKind::Pptx => &["pptx", "pptm", "potx", "ppsx"],
+            FileKind::Zip => &["zip", "epub", "cbz", "apk", "jar", "xpi", "kmz"],
+            FileKind::Ogg => &["ogg", "oga", "ogv"],
+            FileKind::Opus => &["opus", "ogg", "oga"],
+            FileKind::M4a => &["m4a", "mp4", "aac"],
+            FileKind::Mp4 => &["mp4", "m4v"],
+            FileKind::ThreeGp => &["3gp", "3gpp", "3g2"],
+            // Plain text comes in many flavors (csv, vcf, json, ...)
+            FileKind::Text => return !ext.is_empty(),
+            _ => &[],
+        };
+
+        ext == self.extension() || aliases.contains(&ext.as_str())
+    }
+}
+
+/// A header signature: every `(offset, bytes)` part must match
+type Signature = (&'static [(usize, &'static [u8])], FileKind);
+
+/// Fixed-offset signatures, checked in order; more specific entries come first
+const SIGNATURES: &[Signature] = &[
+    (&[(0, b"%PDF-")], FileKind::Pdf),
+    (&[(0, b"\x89PNG\r\n\x1a\n")], FileKind::Png),
+    (&[(0, b"\xFF\xD8\xFF")], FileKind::Jpeg),
+    (&[(0, b"GIF87a")], FileKind::Gif),
+    (&[(0, b"GIF89a")], FileKind::Gif),
+    (&[(0, b"RIFF"), (8, b"WEBP")], FileKind::WebP),
+    (&[(0, b"RIFF"), (8, b"WAVE")], FileKind::Wav),
+    (&[(0, b"RIFF"), (8, b"AVI ")], FileKind::Avi),
+    (&[(0, b"OggS"), (28, b"OpusHead")], FileKind::Opus),
+    (&[(0, b"OggS")], FileKind::Ogg),
+    (&[(0, b"ID3")], FileKind::Mp3),
+    (&[(4, b"ftypheic")], FileKind::Heic),
+    (&[(4, b"ftypheix")], FileKind::Heic),
+    (&[(4, b"ftyphevc")], FileKind::Heic),
+    (&[(4, b"ftyphevx")], FileKind::Heic),
+    (&[(4, b"ftypavif")], FileKind::Avif),
+    (&[(4, b"ftypavis")], FileKind::Avif),
+    // Generic HEIF brands, used by HEIC and AVIF alike; see `heif_kind`
+    (&[(4, b"ftypmif1")], FileKind::Heic),
+    (&[(4, b"ftypmsf1")], FileKind::Heic),
+    (&[(4, b"ftyp3g")], FileKind::ThreeGp),
+    (&[(4, b"ftypM4A ")], FileKind::M4a),
+    (&[(4, b"ftypqt  ")], FileKind::Mov),
+    (&[(4, b"ftyp")], FileKind::Mp4),
+    (&[(0, b"PK\x03\x04")], FileKind::Zip),
+    (&[(0, b"PK\x05\x06")], FileKind::Zip),
+];
+
+fn signature_matches(parts: &[(usize, &[u8])], header: &[u8]) -> bool {
+    parts.iter().all(|(offset, bytes)| {
+        header
+            .get(*offset..*offset + bytes.len())
+            .map(|slice| slice == *bytes)
+            .unwrap_or(false)
+    })
+}
+
+/// Detect the file type from the first bytes of a file.
+///
+/// ZIP files are only refined into ODF documents here (their `mimetype` entry
+/// is stored first); OOXML needs the central directory, see [`sniff_file`].
+pub fn sniff_bytes(header: &[u8]) -> Option<FileKind> {
+    if let Some((_, kind)) = SIGNATURES
+        .iter()
+        .find(|(parts, _)| signature_matches(parts, header))
+    {
+        return Some(match kind {
+            FileKind::Zip => odf_kind(header).unwrap_or(FileKind::Zip),
+            FileKind::Heic => heif_kind(header),
+            _ => *kind,
+        });
+    }
+
+    if is_mpeg_audio_frame(header) {
+        return Some(FileKind::Mp3);
+    }
+
+    if is_text(header) {
+        return Some(FileKind::Text);
+    }
+
+    None
+}
+
+/// Detect the type of a file on disk
+pub fn sniff_file(path: &Path) -> Option<FileKind> {
+    let mut file = File::open(path).ok()?;
+
+    let mut header = Vec::new();
+    file.by_ref()
+        .take(HEADER_LEN)
+        .read_to_end(&mut header)
+        .ok()?;
+
+    let kind = sniff_bytes(&header)?;
+    if kind != FileKind::Zip {
+        return Some(kind);
+    }
+
+    let names = read_zip_entry_names(&mut file).unwrap_or_default();
+    Some(ooxml_kind(&names).unwrap_or(FileKind::Zip))
+}
+
+/// The extension a file should get, if the one it has (or its lack of one)
+/// doesn't fit its contents
+pub fn corrected_extension(path: &Path) -> Option<&'static str> {
+    let kind = sniff_file(path)?;
+    let current = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    (!kind.accepts_extension(current)).then(|| kind.extension())
+}
+
+/// Raw MP3 without an ID3 tag starts with an MPEG audio frame sync
+fn is_mpeg_audio_frame(header: &[u8]) -> bool {
+    match header {
+        [0xFF, b1, b2, ..] => {
+            let sync = b1 & 0xE0 == 0xE0;
+            // Layer bits 00 are reserved (and used by AAC ADTS)
+            let layer = (b1 >> 1) & 0x03 != 0;
+            let bitrate = b2 >> 4 != 0x0F;
+            sync && layer && bitrate
+        }
+        _ => false,
+    }
+}
+
+/// Valid UTF-8 without control characters other than whitespace
+fn is_text(header: &[u8]) -> bool {
+    if header.is_empty() {
+        return false;
+    }
+
+    let text = match std::str::from_utf8(header) {
+        Ok(text) => text,
+        // A multi-byte character may be cut off at the end of the header
+        Err(e) if e.error_len().is_none() && header.len() - e.valid_up_to() < 4 => {
+            std::str::from_utf8(&header[..e.valid_up_to()]).unwrap_or("")
+        }
+        Err(_) => return false,
+    };
+
+    !text.is_empty()
+        && text
+            .chars()
+            .all(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t' | '\x0C'))
+}
+
+/// HEIC or AVIF, by the compatible brands of the `ftyp` box: the major
+/// brand may be a generic `mif1` or `msf1`
+fn heif_kind(header: &[u8]) -> FileKind {
+    let box_len = header
+        .get(..4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        .unwrap_or(0);
+    // Size, type, major brand and minor version come before the list
+    let brands = header.get(16..box_len.min(header.len())).unwrap_or(&[]);
+
+    if brands
+        .chunks_exact(4)
+        .any(|brand| brand == b"avif" || brand == b"avis")
+    {
+        FileKind::Avif
+    } else {
+        FileKind::Heic
+    }
+}
+
+/// ODF documents start with an uncompressed `mimetype` entry
+fn odf_kind(header: &[u8]) -> Option<FileKind> {
+    let u16_at = |at: usize| -> Option<usize> {
+        header
+            .get(at..at + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+    };
+
+    let name_len = u16_at(26)?;
+    let extra_len = u16_at(28)?;
+    if header.get(30..30 + name_len)? != b"mimetype" {
+        return None;
+    }
+
+    let data_start = 30 + name_len + extra_len;
+    let data = header.get(data_start..(data_start + 64).min(header.len()))?;
+
+    if data.starts_with(b"application/vnd.oasis.opendocument.text") {
+        Some(FileKind::Odt)
+    } else if data.starts_with(b"application/vnd.oasis.opendocument.spreadsheet") {
+        Some(FileKind::Ods)
+    } else if data.starts_with(b"application/vnd.oasis.opendocument.presentation") {
+        Some(FileKind::Odp)
+    } else {
+        None
+    }
+}
+
+/// Tell Word, Excel and PowerPoint files apart by their part names
+pub fn ooxml_kind(entry_names: &[String]) -> Option<FileKind> {
+    if !entry_names.iter().any(|n| n == "[Content_Types].xml") {
+        return None;
+    }
+
+    entry_names.iter().find_map(|name| {
+        if name.starts_with("word/") {
+            Some(FileKind::Docx)
+        } else if name.starts_with("xl/") {
+            Some(FileKind::Xlsx)
+        } else if name.starts_with("ppt/") {
+            Some(FileKind::Pptx)
+        } else {
+            None
+        }
+    })
+}
+
+/// List the entries of a ZIP file from its central directory
+fn read_zip_entry_names(file: &mut File) -> Option<Vec<String>> {
+    let len = file.seek(SeekFrom::End(0)).ok()?;
+
+    // End of central directory record: 22 bytes plus an optional comment
+    let tail_len = len.min(22 + u16::MAX as u64);
+    file.seek(SeekFrom::Start(len - tail_len)).ok()?;
+    let mut tail = Vec::new();
+    file.by_ref().take(tail_len).read_to_end(&mut tail).ok()?;
+
+    let eocd = tail.windows(4).rposition(|w| w == b"PK\x05\x06")?;
+    let record = tail.get(eocd..eocd + 22)?;
+    let cd_size = u32::from_le_bytes(record[12..16].try_into().ok()?) as u64;
+    let cd_offset = u32::from_le_bytes(record[16..20].try_into().ok()?) as u64;
+
+    if cd_size > MAX_CENTRAL_DIRECTORY || cd_offset + cd_size > len {
+        return None;
+    }
+
+    file.seek(SeekFrom::Start(cd_offset)).ok()?;
+    let mut directory = Vec::new();
+    file.by_ref()
+        .take(cd_size)
+        .read_to_end(&mut directory)
+        .ok()?;
+
+    Some(central_directory_names(&directory))
+}
+
+/// Parse the file names out of raw central directory records
+fn central_directory_names(directory: &[u8]) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut pos = 0;
+
+    while let Some(entry) = directory.get(pos..pos + 46) {
+        if &entry[0..4] != b"PK\x01\x02" {
+            break;
+        }
+
+        let name_len = u16::from_le_bytes([entry[28], entry[29]]) as usize;
+        let extra_len = u16::from_le_bytes([entry[30], entry[31]]) as usize;
+        let comment_len = u16::from_le_bytes([entry[32], entry[33]]) as usize;
+
+        match directory.get(pos + 46..pos + 46 + name_len) {
+            Some(name) => names.push(String::from_utf8_lossy(name).into_owned()),
+            None => break,
+        }
+
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A stored (uncompressed) ZIP archive with the given entries
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut central = Vec::new();
+
+        for (name, data) in entries {
+            let offset = out.len() as u32;
+            let size = (data.len() as u32).to_le_bytes();
+            let name_len = (name.len() as u16).to_le_bytes();
+
+            out.extend_from_slice(b"PK\x03\x04");
+            // version, flags, method (stored), time, date, crc
+            out.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            out.extend_from_slice(&size);
+            out.extend_from_slice(&size);
+            out.extend_from_slice(&name_len);
+            out.extend_from_slice(&[0, 0]);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(data);
+
+            central.extend_from_slice(b"PK\x01\x02");
+            // version made by, version needed, flags, method, time, date, crc
+            central.extend_from_slice(&[20, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            central.extend_from_slice(&size);
+            central.extend_from_slice(&size);
+            central.extend_from_slice(&name_len);
+            // extra, comment, disk, internal and external attributes
+            central.extend_from_slice(&[0; 12]);
+            central.extend_from_slice(&offset.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+
+        let cd_offset = out.len() as u32;
+        let count = (entries.len() as u16).to_le_bytes();
+        out.extend_from_slice(&central);
+        out.extend_from_slice(b"PK\x05\x06");
+        out.extend_from_slice(&[0, 0, 0, 0]);
+        out.extend_from_slice(&count);
+        out.extend_from_slice(&count);
+        out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        out.extend_from_slice(&cd_offset.to_le_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out
+    }
+
+    fn ooxml(part: &str) -> Vec<u8> {
+        zip(&[
+            ("[Content_Types].xml", b"<Types/>"),
+            ("_rels/.rels", b"<Relationships/>"),
+            (part, b"<xml/>"),
+        ])
+    }
+
+    fn odf(mime: &str) -> Vec<u8> {
+        zip(&[("mimetype", mime.as_bytes()), ("content.xml", b"<xml/>")])
+    }
+
+    fn riff(form: &[u8; 4]) -> Vec<u8> {
+        let mut data = b"RIFF\x24\x00\x00\x00".to_vec();
+        data.extend_from_slice(form);
+        data.extend_from_slice(&[0; 32]);
+        data
+    }
+
+    fn ftyp(brand: &[u8; 4]) -> Vec<u8> {
+        let mut data = b"\x00\x00\x00\x18ftyp".to_vec();
+        data.extend_from_slice(brand);
+        data.extend_from_slice(&[0; 16]);
+        data
+    }
+
+    /// An `ftyp` box with compatible brands
+    fn ftyp_brands(major: &[u8; 4], compatible: &[&[u8; 4]]) -> Vec<u8> {
+        let len = 16 + 4 * compatible.len() as u32;
+        let mut data = len.to_be_bytes().to_vec();
+        data.extend_from_slice(b"ftyp");
+        data.extend_from_slice(major);
+        data.extend_from_slice(&[0; 4]);
+        for brand in compatible {
+            data.extend_from_slice(*brand);
+        }
+        // The next box must not count as a brand
+        data.extend_from_slice(b"\x00\x00\x00\x08avif");
+        data
+    }
+
+    fn ogg(codec_header: &[u8]) -> Vec<u8> {
+        let mut data = b"OggS".to_vec();
+        data.resize(28, 0);
+        data.extend_from_slice(codec_header);
+        data.extend_from_slice(&[0; 16]);
+        data
+    }
+
+    fn write_temp(case: usize, name: &str, contents: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("whatsapp-sniff-test-{}", std::process::id()))
+            .join(case.to_string());
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn sniffs_and_corrects_extensions() {
+        let pdf = b"%PDF-1.7\n%\xE2\xE3\xCF\xD3\n1 0 obj".to_vec();
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR".to_vec();
+        let jpeg = b"\xFF\xD8\xFF\xE0\x00\x10JFIF\x00".to_vec();
+        let gif = b"GIF89a\x01\x00\x01\x00".to_vec();
+        let docx = ooxml("word/document.xml");
+        let xlsx = ooxml("xl/workbook.xml");
+        let pptx = ooxml("ppt/presentation.xml");
+        let odt = odf("application/vnd.oasis.opendocument.text");
+        let plain_zip = zip(&[("photos/a.jpg", b"\xFF\xD8\xFF")]);
+        let text = "Einkaufsliste: Äpfel, Brot\nMilch\n".as_bytes().to_vec();
+
+        // File name, contents, sniffed kind, corrected extension
+        type Case<'a> = (&'a str, &'a [u8], Option<FileKind>, Option<&'a str>);
+        let cases: &[Case] = &[
+            // PDF
+            ("invoice.pdf", &pdf, Some(FileKind::Pdf), None),
+            ("invoice.bin", &pdf, Some(FileKind::Pdf), Some("pdf")),
+            ("invoice", &pdf, Some(FileKind::Pdf), Some("pdf")),
+            // ZIP containers
+            ("report.docx", &docx, Some(FileKind::Docx), None),
+            ("report.zip", &docx, Some(FileKind::Docx), Some("docx")),
+            ("report", &docx, Some(FileKind::Docx), Some("docx")),
+            ("budget.xlsx", &xlsx, Some(FileKind::Xlsx), None),
+            ("budget.docx", &xlsx, Some(FileKind::Xlsx), Some("xlsx")),
+            ("budget", &xlsx, Some(FileKind::Xlsx), Some("xlsx")),
+            ("slides.pptx", &pptx, Some(FileKind::Pptx), None),
+            ("slides", &pptx, Some(FileKind::Pptx), Some("pptx")),
+            ("letter.odt", &odt, Some(FileKind::Odt), None),
+            ("letter.zip", &odt, Some(FileKind::Odt), Some("odt")),
+            ("holiday.zip", &plain_zip, Some(FileKind::Zip), None),
+            ("holiday.docx", &plain_zip, Some(FileKind::Zip), Some("zip")),
+            ("holiday", &plain_zip, Some(FileKind::Zip), Some("zip")),
+            // Images
+            ("photo.png", &png, Some(FileKind::Png), None),
+            ("photo.jpg", &png, Some(FileKind::Png), Some("png")),
+            ("photo", &png, Some(FileKind::Png), Some("png")),
+            ("camera.jpeg", &jpeg, Some(FileKind::Jpeg), None),
+            ("camera.png", &jpeg, Some(FileKind::Jpeg), Some("jpg")),
+            ("camera", &jpeg, Some(FileKind::Jpeg), Some("jpg")),
+            ("funny.gif", &gif, Some(FileKind::Gif), None),
+            ("funny.mp4", &gif, Some(FileKind::Gif), Some("gif")),
+            ("sticker.webp", &riff(b"WEBP"), Some(FileKind::WebP), None),
+            (
+                "sticker",
+                &riff(b"WEBP"),
+                Some(FileKind::WebP),
+                Some("webp"),
+            ),
+            ("scan.heic", &ftyp(b"heic"), Some(FileKind::Heic), None),
+            (
+                "scan.heic",
+                &ftyp_brands(b"mif1", &[b"mif1", b"heic"]),
+                Some(FileKind::Heic),
+                None,
+            ),
+            ("photo.avif", &ftyp(b"avif"), Some(FileKind::Avif), None),
+            (
+                "photo.heic",
+                &ftyp_brands(b"mif1", &[b"mif1", b"avif", b"miaf"]),
+                Some(FileKind::Avif),
+                Some("avif"),
+            ),
+            (
+                "animation",
+                &ftyp_brands(b"msf1", &[b"msf1", b"avis"]),
+                Some(FileKind::Avif),
+                Some("avif"),
+            ),
+            (
+                "scan.jpg",
+                &ftyp(b"heic"),
+                Some(FileKind::Heic),
+                Some("heic"),
+            ),
+            // RIFF files are not all WebP
+            (
+                "memo.webp",
+                &riff(b"WAVE"),
+                Some(FileKind::Wav),
+                Some("wav"),
+            ),
+            (
+                "clip.webp",
+                &riff(b"AVI "),
+                Some(FileKind::Avi),
+                Some("avi"),
+            ),
+            // Voice notes, audio and video
+            (
+                "PTT-20240101-WA0001.ogg",
+                &ogg(b"OpusHead"),
+                Some(FileKind::Opus),
+                None,
+            ),
+            (
+                "PTT-20240101-WA0001",
+                &ogg(b"OpusHead"),
+                Some(FileKind::Opus),
+                Some("opus"),
+            ),
+            ("song.ogg", &ogg(b"\x01vorbis"), Some(FileKind::Ogg), None),
+            (
+                "song.mp3",
+                b"ID3\x04\x00\x00\x00\x00\x00\x00",
+                Some(FileKind::Mp3),
+                None,
+            ),
+            (
+                "song",
+                b"\xFF\xFB\x90\x44\x00",
+                Some(FileKind::Mp3),
+                Some("mp3"),
+            ),
+            ("video.mp4", &ftyp(b"isom"), Some(FileKind::Mp4), None),
+            ("video", &ftyp(b"isom"), Some(FileKind::Mp4), Some("mp4")),
+            ("video.3gp", &ftyp(b"3gp5"), Some(FileKind::ThreeGp), None),
+            (
+                "video.mp4",
+                &ftyp(b"3gp5"),
+                Some(FileKind::ThreeGp),
+                Some("3gp"),
+            ),
+            // Empty files can't be sniffed and are left alone
+            ("empty.pdf", b"", None, None),
+            ("empty", b"", None, None),
+            // Plain text keeps any extension, but gets one if it has none
+            ("notes.txt", &text, Some(FileKind::Text), None),
+            ("contacts.vcf", &text, Some(FileKind::Text), None),
+            ("notes", &text, Some(FileKind::Text), Some("txt")),
+            // Unknown binary
+            ("blob.dat", b"\x00\x01\x02\x03\xFE", None, None),
+        ];
+
+        for (i, (name, contents, kind, corrected)) in cases.iter().enumerate() {
+            let path = write_temp(i, name, contents);
+            assert_eq!(sniff_file(&path), *kind, "sniff_file({})", name);
+            assert_eq!(
+                corrected_extension(&path),
+                *corrected,
+                "corrected_extension({})",
+                name
+            );
+        }
+
+        let _ = fs::remove_dir_all(
+            std::env::temp_dir().join(format!("whatsapp-sniff-test-{}", std::process::id())),
+        );
+    }
+
+    #[test]
+    fn sniff_bytes_needs_no_directory() {
+        // OOXML needs the central directory, so from the header alone it's a ZIP
+        assert_eq!(
+            sniff_bytes(&ooxml("word/document.xml")),
+            Some(FileKind::Zip)
+        );
+        assert_eq!(
+            sniff_bytes(&odf("application/vnd.oasis.opendocument.spreadsheet")),
+            Some(FileKind::Ods)
+        );
+        assert_eq!(sniff_bytes(b""), None);
+        // A multi-byte character cut off at the end of the header
+        let text = "naïve caf\u{e9}".as_bytes();
+        assert_eq!(sniff_bytes(&text[..text.len() - 1]), Some(FileKind::Text));
+        assert_eq!(sniff_bytes(&"é".as_bytes()[..1]), None);
+    }
+}
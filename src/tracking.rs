@@ -0,0 +1,345 @@
+//! Link cleaning before links leave the app
+//!
+//! Redirector links such as `https://l.facebook.com/l.php?u=...` are
+//! unwrapped to the link they point to, then tracking query parameters
+//! (`utm_*`, `fbclid`, `gclid`, ...) are removed. Both steps are controlled
+//! by [`LinkCleaning`]. Parameters that are kept stay byte-for-byte as they
+//! were, so signed or oddly encoded links keep working.
+
+use serde::{Deserialize, Serialize};
+
+/// Redirectors wrapping redirectors are unwrapped at most this deep
+const MAX_UNWRAP: usize = 5;
+
+/// A redirector whose query parameter holds the real link
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Redirector {
+    pub host: String,
+    /// Path the redirector uses, e.g. `/l.php`; `None` matches any path
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Parameters that may hold the target, tried in order
+    pub params: Vec<String>,
+}
+
+impl Redirector {
+    fn new(host: &str, path: Option<&str>, params: &[&str]) -> Self {
+        Self {
+            host: host.to_string(),
+            path: path.map(str::to_string),
+            params: params.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+}
+
+/// Link cleaning settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LinkCleaning {
+    /// Remove tracking parameters
+    pub strip_params: bool,
+    /// Parameter names to remove, case-insensitive; a trailing `*` matches
+    /// any suffix
+    pub params: Vec<String>,
+    /// Replace redirector links with the link they point to
+    pub unwrap_redirects: bool,
+    pub redirectors: Vec<Redirector>,
+}
+
+impl Default for LinkCleaning {
+    fn default() -> Self {
+        Self {
+            strip_params: true,
+            params: default_params(),
+            unwrap_redirects: true,
+            redirectors: default_redirectors(),
+        }
+    }
+}
+
+pub fn default_params() -> Vec<String> {
+    [
+        "utm_*",
+        "fbclid",
+        "gclid",
+        "dclid",
+        "gbraid",
+        "wbraid",
+        "msclkid",
+        "yclid",
+        "twclid",
+        "ttclid",
+        "igshid",
+        "igsh",
+        "si",
+        "mc_cid",
+        "mc_eid",
+        "_ga",
+        "_gl",
+        "_hsenc",
+        "_hsmi",
+        "mkt_tok",
+        "li_fat_id",
+        "ref_src",
+        "oly_anon_id",
+        "oly_enc_id",
+        "vero_id",
+    ]
+    .iter()
+    .map(|p| p.to_string())
+    .collect()
+}
+
+pub fn default_redirectors() -> Vec<Redirector> {
+    vec![
+        Redirector::new("l.facebook.com", Some("/l.php"), &["u"]),
+        Redirector::new("lm.facebook.com", Some("/l.php"), &["u"]),
+        Redirector::new("l.messenger.com", Some("/l.php"), &["u"]),
+        Redirector::new("l.instagram.com", None, &["u"]),
+        Redirector::new("l.wl.co", Some("/l"), &["u"]),
+        Redirector::new("www.google.com", Some("/url"), &["q", "url"]),
+        Redirector::new("google.com", Some("/url"), &["q", "url"]),
+        Redirector::new("www.youtube.com", Some("/redirect"), &["q"]),
+        Redirector::new("out.reddit.com", None, &["url"]),
+        Redirector::new("t.umblr.com", Some("/redirect"), &["z"]),
+        Redirector::new("slack-redir.net", Some("/link"), &["url"]),
+    ]
+}
+
+impl LinkCleaning {
+    /// The cleaned link; unchanged if it isn't an http(s) link
+    pub fn clean(&self, link: &str) -> String {
+        let Ok(mut url) = url::Url::parse(link) else {
+            return link.to_string();
+        };
+        if !is_web(&url) {
+            return link.to_string();
+        }
+        let original = url.clone();
+
+        if self.unwrap_redirects {
+            for _ in 0..MAX_UNWRAP {
+                match self.unwrap(&url) {
+                    Some(inner) => url = inner,
+                    None => break,
+                }
+            }
+        }
+
+        if self.strip_params {
+            self.strip(&mut url);
+        }
+
+        // Don't let re-serializing touch links that needed no cleaning
+        if url == original {
+            link.to_string()
+        } else {
+            url.to_string()
+        }
+    }
+
+    /// The link a redirector points to, if `url` is one
+    fn unwrap(&self, url: &url::Url) -> Option<url::Url> {
+        let host = url.host_str()?.to_lowercase();
+        let redirector = self.redirectors.iter().find(|r| {
+            r.host.eq_ignore_ascii_case(&host)
+                && r.path.as_deref().is_none_or(|path| url.path() == path)
+        })?;
+
+        redirector.params.iter().find_map(|param| {
+            let (_, value) = url.query_pairs().find(|(name, _)| name == param.as_str())?;
+            url::Url::parse(&value).ok().filter(is_web)
+        })
+    }
+
+    /// Remove tracking parameters, keeping the others exactly as they were
+    fn strip(&self, url: &mut url::Url) {
+        let Some(query) = url.query() else {
+            return;
+        };
+
+        let kept: Vec<&str> = query
+            .split('&')
+            .filter(|pair| {
+                let name = pair.split('=').next().unwrap_or("");
+                let name = url::form_urlencoded::parse(name.as_bytes())
+                    .next()
+                    .map(|(name, _)| name.into_owned())
+                    .unwrap_or_default();
+                !pair.is_empty() && !self.params.iter().any(|p| param_matches(p, &name))
+            })
+            .collect();
+
+        if kept.is_empty() {
+            url.set_query(None);
+        } else {
+            let kept = kept.join("&");
+            url.set_query(Some(&kept));
+        }
+    }
+}
+
+fn is_web(url: &url::Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+}
+
+/// Case-insensitive name match; `utm_*` matches any `utm_` parameter
+fn param_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.trim().to_lowercase();
+    let name = name.to_lowercase();
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleans_sample_urls() {
+        let cleaning = LinkCleaning::default();
+
+        // (link as sent, link as opened)
+        let corpus = [
+            // Tracking parameters
+            (
+                "https://example.com/article?utm_source=whatsapp&utm_medium=social&utm_campaign=x",
+                "https://example.com/article",
+            ),
+            (
+                "https://example.com/article?id=7&utm_source=whatsapp&page=2",
+                "https://example.com/article?id=7&page=2",
+            ),
+            (
+                "https://example.com/?fbclid=IwAR0abc",
+                "https://example.com/",
+            ),
+            (
+                "https://example.com/?UTM_Source=x&FBCLID=y&q=rust",
+                "https://example.com/?q=rust",
+            ),
+            (
+                "https://shop.example/item?gclid=abc&msclkid=def&color=red",
+                "https://shop.example/item?color=red",
+            ),
+            (
+                "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC?si=0123abcd",
+                "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC",
+            ),
+            (
+                "https://www.instagram.com/p/Cxyz/?igshid=MzRlODBiNWFlZA==",
+                "https://www.instagram.com/p/Cxyz/",
+            ),
+            // Fragments survive
+            (
+                "https://example.com/docs?utm_source=wa#section-2",
+                "https://example.com/docs#section-2",
+            ),
+            (
+                "https://example.com/app?fbclid=x#/route?utm_source=kept",
+                "https://example.com/app#/route?utm_source=kept",
+            ),
+            // Redirectors
+            (
+                "https://l.facebook.com/l.php?u=https%3A%2F%2Fexample.com%2Fnews%3Fid%3D5&h=AT0xyz",
+                "https://example.com/news?id=5",
+            ),
+            (
+                "https://lm.facebook.com/l.php?u=https%3A%2F%2Fexample.com%2F%3Ffbclid%3Dabc",
+                "https://example.com/",
+            ),
+            (
+                "https://l.instagram.com/?u=https%3A%2F%2Fexample.org%2Fshop&e=ATM",
+                "https://example.org/shop",
+            ),
+            (
+                "https://www.google.com/url?sa=t&url=https%3A%2F%2Fexample.com%2Fpaper.pdf&usg=AOv",
+                "https://example.com/paper.pdf",
+            ),
+            (
+                "https://www.google.com/url?q=https://example.com/a%3Fb%3Dc&sa=D",
+                "https://example.com/a?b=c",
+            ),
+            (
+                "https://www.youtube.com/redirect?event=video_description&q=https%3A%2F%2Fexample.com%2Fshop",
+                "https://example.com/shop",
+            ),
+            (
+                "https://out.reddit.com/t3_abc?url=https%3A%2F%2Fexample.com%2Fpost&token=x",
+                "https://example.com/post",
+            ),
+            // Redirector wrapping a redirector
+            (
+                "https://l.facebook.com/l.php?u=https%3A%2F%2Fwww.google.com%2Furl%3Fq%3Dhttps%253A%252F%252Fexample.com%252Fdeep%26sa%3DD",
+                "https://example.com/deep",
+            ),
+            // A redirector pointing at something that isn't a web link stays
+            (
+                "https://l.facebook.com/l.php?u=javascript%3Aalert(1)",
+                "https://l.facebook.com/l.php?u=javascript%3Aalert(1)",
+            ),
+            // Only the redirector's own path unwraps
+            (
+                "https://www.google.com/search?q=https%3A%2F%2Fexample.com",
+                "https://www.google.com/search?q=https%3A%2F%2Fexample.com",
+            ),
+            // Shorteners can't be resolved offline; only their parameters go
+            ("https://bit.ly/3xYzAbC", "https://bit.ly/3xYzAbC"),
+            (
+                "https://t.co/AbCdEf123?utm_source=whatsapp",
+                "https://t.co/AbCdEf123",
+            ),
+            // Links that must not change, byte for byte
+            ("https://example.com/", "https://example.com/"),
+            ("https://example.com", "https://example.com"),
+            ("https://EXAMPLE.com/Path", "https://EXAMPLE.com/Path"),
+            (
+                "https://example.com/search?q=caf%C3%A9+cr%C3%A8me&lang=fr",
+                "https://example.com/search?q=caf%C3%A9+cr%C3%A8me&lang=fr",
+            ),
+            (
+                "https://s3.example.com/f.pdf?X-Amz-Signature=ab%2Fcd&X-Amz-Expires=60",
+                "https://s3.example.com/f.pdf?X-Amz-Signature=ab%2Fcd&X-Amz-Expires=60",
+            ),
+            (
+                "https://example.com/?utm=not-a-prefix-match&site=1",
+                "https://example.com/?utm=not-a-prefix-match&site=1",
+            ),
+            ("mailto:someone@example.com?subject=hi", "mailto:someone@example.com?subject=hi"),
+            ("tel:+15551234567", "tel:+15551234567"),
+            ("not a url", "not a url"),
+        ];
+
+        for (link, expected) in corpus {
+            assert_eq!(cleaning.clean(link), expected, "{}", link);
+        }
+    }
+
+    #[test]
+    fn each_step_can_be_turned_off() {
+        let link = "https://l.facebook.com/l.php?u=https%3A%2F%2Fexample.com%2F%3Futm_source%3Dfb";
+
+        let no_unwrap = LinkCleaning {
+            unwrap_redirects: false,
+            ..LinkCleaning::default()
+        };
+        assert_eq!(no_unwrap.clean(link), link);
+
+        let no_strip = LinkCleaning {
+            strip_params: false,
+            ..LinkCleaning::default()
+        };
+        assert_eq!(no_strip.clean(link), "https://example.com/?utm_source=fb");
+
+        let custom = LinkCleaning {
+            params: vec!["ref".to_string()],
+            ..LinkCleaning::default()
+        };
+        assert_eq!(
+            custom.clean("https://example.com/?ref=wa&utm_source=x"),
+            "https://example.com/?utm_source=x"
+        );
+    }
+}